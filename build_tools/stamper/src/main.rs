@@ -0,0 +1,255 @@
+//explicit returns, like the shipper itself.
+#![allow(clippy::needless_return)]
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Start of the tag in front of every placeholder, "PAKKLY_SLOT[NAME;LENGTH]:" followed by LENGTH bytes of value.
+static TAG_START: &[u8] = b"PAKKLY_SLOT[";
+/// The slot holding the versioned config blob rather than a padded string, see src/config.rs.
+static CONFIG_SLOT: &str = "CONFIG";
+static CONFIG_FORMAT_JSON: u8 = 1;
+
+static USAGE: &str = "Usage:
+    stamper list <shipper>
+        prints every slot and its current value
+    stamper stamp <shipper> [--output <path>] NAME=VALUE...
+        fills the named legacy slots, in place unless --output is given
+    stamper config <shipper> [--output <path>] <config.json>
+        stores a JSON object in the config blob, which takes precedence over the legacy slots";
+
+struct Slot {
+    name: String,
+    //where the value starts, right after the tag
+    offset: usize,
+    length: usize,
+}
+impl Slot {
+    /// The value as the shipper reads it, padding removed. The config blob reads as its JSON.
+    fn value(&self, binary: &[u8]) -> Result<String, String> {
+        if self.name == CONFIG_SLOT {
+            return Ok(match read_config(&binary[self.offset..self.offset + self.length])? {
+                Some(x) => x.to_string(),
+                None => String::new(),
+            });
+        }
+        let raw: Vec<u8> = binary[self.offset..self.offset + self.length].iter().copied().filter(|x| *x != 0).collect();
+        return String::from_utf8(raw).map_err(|_| format!("Slot {} does not hold valid UTF-8", self.name));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|x| x.as_str()) {
+        Some("list") if args.len() == 2 => list(Path::new(&args[1])),
+        Some("stamp") if args.len() >= 3 => stamp(&args[1..]),
+        Some("config") if args.len() >= 3 => stamp_config(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn list(path: &Path) -> Result<(), String> {
+    let binary = read(path)?;
+    for slot in find_slots(&binary)? {
+        println!("{}={} ({} bytes max)", slot.name, slot.value(&binary)?, slot.length);
+    }
+    return Ok(());
+}
+
+/// Splits off --output, returns (input, output, the remaining arguments).
+fn parse_paths(args: &[String]) -> Result<(PathBuf, PathBuf, Vec<String>), String> {
+    let input = PathBuf::from(&args[0]);
+    let mut output = input.clone();
+    let mut rest: Vec<String> = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--output" {
+            output = PathBuf::from(args.get(i + 1).ok_or("--output needs a path")?);
+            i += 2;
+            continue;
+        }
+        rest.push(args[i].clone());
+        i += 1;
+    }
+    return Ok((input, output, rest));
+}
+
+fn stamp(args: &[String]) -> Result<(), String> {
+    let (input, output, rest) = parse_paths(args)?;
+    let mut values: Vec<(String, String)> = Vec::new();
+    for arg in rest {
+        match arg.split_once('=') {
+            Some((name, value)) => values.push((name.to_string(), value.to_string())),
+            None => return Err(format!("Expected NAME=VALUE, got {:?}", arg)),
+        }
+    }
+    if values.is_empty() {
+        return Err("Nothing to stamp".to_string());
+    }
+
+    let mut binary = read(&input)?;
+    let slots: HashMap<String, Slot> = find_slots(&binary)?.into_iter().map(|x| (x.name.clone(), x)).collect();
+    for (name, value) in &values {
+        let slot = match slots.get(name) {
+            Some(x) => x,
+            None => {
+                let mut known: Vec<&String> = slots.keys().collect();
+                known.sort();
+                return Err(format!("Unknown slot {}, the binary has {:?}", name, known));
+            }
+        };
+        if name == CONFIG_SLOT {
+            return Err("The config blob is written with the config command".to_string());
+        }
+        if value.is_empty() {
+            return Err(format!("Value for {} is empty", name));
+        }
+        if value.contains('\0') {
+            return Err(format!("Value for {} contains NUL, which is the padding", name));
+        }
+        if value.len() > slot.length {
+            return Err(format!("Value for {} is {} bytes, the slot holds {}", name, value.len(), slot.length));
+        }
+        let region = &mut binary[slot.offset..slot.offset + slot.length];
+        region.fill(0x00);
+        region[..value.len()].copy_from_slice(value.as_bytes());
+    }
+    return write_verified(&input, &output, &binary, &values);
+}
+
+fn stamp_config(args: &[String]) -> Result<(), String> {
+    let (input, output, rest) = parse_paths(args)?;
+    if rest.len() != 1 {
+        return Err("Expected exactly one config file".to_string());
+    }
+    let config_path = Path::new(&rest[0]);
+    let config: serde_json::Value = serde_json::from_slice(&read(config_path)?)
+        .map_err(|e| format!("{:?} is not valid JSON: {}", config_path, e))?;
+    if !config.is_object() {
+        return Err("The config has to be a JSON object".to_string());
+    }
+    //compact, every byte of the slot counts.
+    let payload = config.to_string();
+
+    let mut binary = read(&input)?;
+    let slots = find_slots(&binary)?;
+    let slot = match slots.iter().find(|x| x.name == CONFIG_SLOT) {
+        Some(x) => x,
+        None => return Err("This shipper predates the config blob, stamp its legacy slots instead".to_string()),
+    };
+    if 5 + payload.len() > slot.length {
+        return Err(format!("Config is {} bytes, the slot holds {}", payload.len(), slot.length - 5));
+    }
+    let region = &mut binary[slot.offset..slot.offset + slot.length];
+    region.fill(0x00);
+    region[0] = CONFIG_FORMAT_JSON;
+    region[1..5].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    region[5..5 + payload.len()].copy_from_slice(payload.as_bytes());
+    return write_verified(&input, &output, &binary, &[(CONFIG_SLOT.to_string(), payload)]);
+}
+
+/// Replaces output with binary once reading it back from disk shows every slot in values holds its value.
+fn write_verified(input: &Path, output: &Path, binary: &[u8], values: &[(String, String)]) -> Result<(), String> {
+    //written next to the output first, a half written shipper must never take its place.
+    let temporary = output.with_file_name(format!(
+        "{}.stamper_tmp",
+        output.file_name().ok_or("Output has no file name")?.to_string_lossy()
+    ));
+    fs::write(&temporary, binary).map_err(|e| format!("Could not write {:?}: {}", temporary, e))?;
+    let permissions = fs::metadata(input).map_err(|e| e.to_string())?.permissions();
+    fs::set_permissions(&temporary, permissions).map_err(|e| e.to_string())?;
+    if let Err(e) = verify(&temporary, binary.len(), values) {
+        let _e = fs::remove_file(&temporary);
+        return Err(e);
+    }
+    fs::rename(&temporary, output).map_err(|e| format!("Could not replace {:?}: {}", output, e))?;
+    for (name, value) in values {
+        println!("{}={}", name, value);
+    }
+    return Ok(());
+}
+
+/// Reads what was written back from disk and checks every stamped slot holds exactly its value.
+fn verify(path: &Path, expected_length: usize, values: &[(String, String)]) -> Result<(), String> {
+    let written = read(path)?;
+    if written.len() != expected_length {
+        return Err(format!("Wrote {} bytes instead of {}", written.len(), expected_length));
+    }
+    let slots: HashMap<String, Slot> = find_slots(&written)?.into_iter().map(|x| (x.name.clone(), x)).collect();
+    for (name, value) in values {
+        let found = match slots.get(name) {
+            Some(x) => x.value(&written)?,
+            None => return Err(format!("Slot {} is gone after stamping", name)),
+        };
+        if found != *value {
+            return Err(format!("Slot {} reads back as {:?} instead of {:?}", name, found, value));
+        }
+    }
+    return Ok(());
+}
+
+/// Mirrors EmbeddedConfig::parse of the shipper, None for a slot never stamped.
+fn read_config(blob: &[u8]) -> Result<Option<serde_json::Value>, String> {
+    if blob[0] == 0 {
+        return Ok(None);
+    }
+    if blob[0] != CONFIG_FORMAT_JSON {
+        return Err(format!("Unknown config format version {}", blob[0]));
+    }
+    let length = u32::from_le_bytes(blob[1..5].try_into().unwrap()) as usize;
+    let payload = blob.get(5..5 + length).ok_or("Config overruns its slot")?;
+    return Ok(Some(serde_json::from_slice(payload).map_err(|e| format!("Config is not valid JSON: {}", e))?));
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    return fs::read(path).map_err(|e| format!("Could not read {:?}: {}", path, e));
+}
+
+/// Every well formed tag in the binary. A slot showing up twice means the tags can't be trusted, so that fails.
+fn find_slots(binary: &[u8]) -> Result<Vec<Slot>, String> {
+    let mut slots: Vec<Slot> = Vec::new();
+    let mut start = 0;
+    while let Some(found) = find(&binary[start..], TAG_START) {
+        let tag = start + found;
+        start = tag + TAG_START.len();
+        if let Some(slot) = parse_tag(binary, start) {
+            if slots.iter().any(|x| x.name == slot.name) {
+                return Err(format!("Slot {} appears more than once, refusing to guess", slot.name));
+            }
+            slots.push(slot);
+        }
+    }
+    if slots.is_empty() {
+        return Err("No slots found, is this a shipper binary?".to_string());
+    }
+    return Ok(slots);
+}
+/// Parses "NAME;LENGTH]:" at offset, None for anything else that happens to follow TAG_START.
+fn parse_tag(binary: &[u8], offset: usize) -> Option<Slot> {
+    let rest = &binary[offset..binary.len().min(offset + 64)];
+    let separator = rest.iter().position(|x| *x == b';')?;
+    let end = rest.iter().position(|x| *x == b']')?;
+    let name = std::str::from_utf8(&rest[..separator]).ok()?;
+    if name.is_empty() || !name.bytes().all(|x| x.is_ascii_uppercase() || x.is_ascii_digit() || x == b'_') {
+        return None;
+    }
+    let length: usize = std::str::from_utf8(rest.get(separator + 1..end)?).ok()?.parse().ok()?;
+    if rest.get(end + 1) != Some(&b':') {
+        return None;
+    }
+    let value_offset = offset + end + 2;
+    if value_offset + length > binary.len() {
+        return None;
+    }
+    return Some(Slot { name: name.to_string(), offset: value_offset, length });
+}
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    return haystack.windows(needle.len()).position(|x| x == needle);
+}
@@ -4,11 +4,12 @@ use std::error::Error;
 use std::fmt::{Debug, Formatter};
 
 pub struct FormattedError {
-    err: Box<dyn Error>,
+    err: Box<dyn Error + Send + Sync>,
     msg: String,
     stacktrace: String,
     pub is_network_error: bool,
     pub is_missing_sudo: bool,
+    pub is_out_of_space: bool,
 }
 impl Debug for FormattedError {
     fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
@@ -27,12 +28,13 @@ impl Default for FormattedError {
             stacktrace: format!("{:?}", Backtrace::new()),
             is_network_error: false,
             is_missing_sudo: false,
+            is_out_of_space: false,
         }
     }
 }
 impl<T> From<T> for FormattedError
 where
-    T: Error + 'static,
+    T: Error + Send + Sync + 'static,
 {
     fn from(err: T) -> Self {
         FormattedError { err: Box::from(err), ..Default::default() }
@@ -48,6 +50,16 @@ impl FormattedError {
     pub fn from_missing_sudo(err: String) -> Self {
         FormattedError { is_missing_sudo: true, msg: err, ..Default::default() }
     }
+    pub fn from_out_of_space(err: String) -> Self {
+        FormattedError { is_out_of_space: true, msg: err, ..Default::default() }
+    }
+    /// The message without the stack trace, for showing to users.
+    pub fn message(&self) -> String {
+        if self.msg != "" {
+            return self.msg.clone();
+        }
+        return self.err.to_string();
+    }
 }
 #[macro_export(local_inner_macros)]
 macro_rules! ferror {
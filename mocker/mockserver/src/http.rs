@@ -0,0 +1,138 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/// Just enough HTTP/1.1 for ureq: one request per connection, bodies by Content-Length.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl Request {
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        return self.query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Response {
+        return Response { status, content_type, body };
+    }
+    pub fn json(body: String) -> Response {
+        return Response::new(200, "application/json", body.into_bytes());
+    }
+    pub fn text(status: u16, body: &str) -> Response {
+        return Response::new(status, "text/plain", body.as_bytes().to_vec());
+    }
+}
+
+/// None when the client connected and went away without sending anything.
+pub fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let length: usize = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    let mut body: Vec<u8> = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (target, Vec::new()),
+    };
+    return Ok(Some(Request { method, path: decode(&path), query, headers, body }));
+}
+
+/// truncate_at cuts the body off after that many bytes while still announcing its full length,
+/// which is what a dropped connection looks like to the client.
+pub fn write_response(stream: &mut TcpStream, response: &Response, truncate_at: Option<usize>) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    let end = truncate_at.unwrap_or(response.body.len()).min(response.body.len());
+    stream.write_all(&response.body[..end])?;
+    stream.flush()?;
+    return Ok(());
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    return query
+        .split('&')
+        .filter(|x| !x.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) => (decode(k), decode(v)),
+            None => (decode(pair), String::new()),
+        })
+        .collect();
+}
+fn decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                    Some(x) => {
+                        out.push(x);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            x => out.push(x),
+        }
+        i += 1;
+    }
+    return String::from_utf8_lossy(&out).to_string();
+}
+fn reason(status: u16) -> &'static str {
+    return match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Status",
+    };
+}
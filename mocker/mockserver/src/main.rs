@@ -0,0 +1,296 @@
+//explicit returns, like the shipper itself.
+#![allow(clippy::needless_return)]
+//! Stands in for the Pakkly server so the shipper can be run end to end without network access.
+//!
+//! Serves /api/v1/shipper/info and /api/v1/shipper/error the way the server does, the app and shipper
+//! payloads from local release folders, and /app_ping for the test app. Every request is recorded.
+//! Responses can be scripted to be slow, fail or break off, see script::Rule.
+//!
+//! Control endpoints, not recorded themselves:
+//! - GET /_mock/requests: the recorded requests as a JSON array
+//! - POST /_mock/rules: replaces the script with the JSON array of rules in the body
+//! - POST /_mock/reset: forgets the recorded requests and the script
+mod http;
+mod payloads;
+mod script;
+
+use http::{Request, Response};
+use payloads::ReleaseDir;
+use script::Rule;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static USAGE: &str = "Usage:
+    mockserver --apps <dir> --shippers <dir> [options]
+        every subdirectory of a release folder is a version, zipped when downloaded,
+        a <version>.zip file next to them is served as it is
+Options:
+    --port <port>                 defaults to 8750, 0 picks a free one
+    --script <rules.json>         JSON array of rules applied from the start
+    --record <requests.jsonl>     also appends every recorded request to this file
+    --app-name <name>             defaults to \"Mock App\"
+    --company-name <name>
+    --background-color <#RRGGBBAA> defaults to #2F2F2FFF";
+
+struct Options {
+    port: u16,
+    apps: ReleaseDir,
+    shippers: ReleaseDir,
+    script: Vec<Rule>,
+    record: Option<PathBuf>,
+    app_name: String,
+    company_name: Option<String>,
+    background_color: String,
+}
+
+/// The parts of the shipper's PakklyMetaRemote the mock fills in.
+#[derive(Serialize)]
+struct MetaRemote {
+    background_color: String,
+    description: Option<String>,
+    shipper: Release,
+    app: Release,
+    app_name: String,
+    company_name: Option<String>,
+}
+#[derive(Serialize)]
+struct Release {
+    url: String,
+    version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    query: BTreeMap<String, String>,
+    //the body as JSON when it parses, as a string otherwise
+    body: serde_json::Value,
+    status: u16,
+    //when the request came in, milliseconds since the epoch
+    at_ms: u128,
+}
+
+struct State {
+    rules: Vec<Rule>,
+    requests: Vec<RecordedRequest>,
+    record_file: Option<File>,
+}
+
+fn main() {
+    let options = match parse_options(std::env::args().skip(1).collect()) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: {}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let listener = match TcpListener::bind(("127.0.0.1", options.port)) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: could not listen on port {}: {}", options.port, e);
+            std::process::exit(1);
+        }
+    };
+    let record_file = options.record.as_ref().map(|path| {
+        OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|e| {
+            eprintln!("error: could not open {:?}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    //the address goes to stdout on its own line, scripts starting the server with port 0 read it from there.
+    println!("http://{}", listener.local_addr().unwrap());
+    let state = Arc::new(Mutex::new(State { rules: options.script.clone(), requests: Vec::new(), record_file }));
+    let options = Arc::new(options);
+    for stream in listener.incoming().flatten() {
+        let (options, state) = (options.clone(), state.clone());
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &options, &state) {
+                eprintln!("connection failed: {}", e);
+            }
+        });
+    }
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut values: BTreeMap<String, String> = BTreeMap::new();
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        let name = match flag.strip_prefix("--") {
+            Some(x) => x.to_string(),
+            None => return Err(format!("Unexpected argument {:?}", flag)),
+        };
+        let value = args.next().ok_or(format!("--{} needs a value", name))?;
+        values.insert(name, value);
+    }
+    let mut take = |name: &str| values.remove(name);
+    let release_dir = |value: Option<String>, name: &str| -> Result<ReleaseDir, String> {
+        let root = PathBuf::from(value.ok_or(format!("--{} is required", name))?);
+        if !root.is_dir() {
+            return Err(format!("{:?} is not a directory", root));
+        }
+        return Ok(ReleaseDir { root });
+    };
+    let script = match take("script") {
+        Some(path) => {
+            let raw = std::fs::read(&path).map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+            serde_json::from_slice(&raw).map_err(|e| format!("{:?} is not a list of rules: {}", path, e))?
+        }
+        None => Vec::new(),
+    };
+    let options = Options {
+        port: match take("port") {
+            Some(x) => x.parse().map_err(|_| format!("Invalid port {:?}", x))?,
+            None => 8750,
+        },
+        apps: release_dir(take("apps"), "apps")?,
+        shippers: release_dir(take("shippers"), "shippers")?,
+        script,
+        record: take("record").map(PathBuf::from),
+        app_name: take("app-name").unwrap_or("Mock App".to_string()),
+        company_name: take("company-name"),
+        background_color: take("background-color").unwrap_or("#2F2F2FFF".to_string()),
+    };
+    if let Some(unknown) = values.keys().next() {
+        return Err(format!("Unknown option --{}", unknown));
+    }
+    return Ok(options);
+}
+
+fn handle(mut stream: TcpStream, options: &Options, state: &Mutex<State>) -> std::io::Result<()> {
+    let request = match http::read_request(&stream)? {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    if request.path.starts_with("/_mock/") {
+        let response = control(&request, state);
+        return http::write_response(&mut stream, &response, None);
+    }
+
+    let rule = script::take_rule(&mut state.lock().unwrap().rules, &request.method, &request.path);
+    let rule = rule.unwrap_or_default();
+    let mut response = match &rule.body {
+        Some(body) => {
+            let content_type = match serde_json::from_str::<serde_json::Value>(body) {
+                Ok(_) => "application/json",
+                Err(_) => "text/plain",
+            };
+            Response::new(200, content_type, body.as_bytes().to_vec())
+        }
+        None => route(&request, options),
+    };
+    if let Some(status) = rule.status {
+        response.status = status;
+    }
+    record(&request, response.status, state);
+    if rule.delay_ms > 0 {
+        std::thread::sleep(Duration::from_millis(rule.delay_ms));
+    }
+    return http::write_response(&mut stream, &response, rule.truncate_at);
+}
+
+fn route(request: &Request, options: &Options) -> Response {
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/v1/shipper/info") => info(request, options),
+        ("POST", "/api/v1/shipper/error") => Ok(Response::text(200, "")),
+        ("GET", "/app_ping") => Ok(Response::text(200, "pong")),
+        ("GET", path) if path.starts_with("/payloads/") => payload(path, options),
+        _ => Ok(Response::text(404, "Not found")),
+    };
+    return match result {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{} {} failed: {}", request.method, request.path, e);
+            Response::text(500, &e)
+        }
+    };
+}
+
+/// Picks the newest app and shipper, or the versions asked for by new_app_version and new_shipper_version.
+fn info(request: &Request, options: &Options) -> Result<Response, String> {
+    let pick = |dir: &ReleaseDir, asked: Option<&str>| -> Result<Option<String>, String> {
+        return match asked {
+            Some(version) => Ok(dir.versions()?.into_iter().find(|x| x == version)),
+            None => dir.latest(),
+        };
+    };
+    let app = pick(&options.apps, request.query_value("new_app_version"))?;
+    let shipper = pick(&options.shippers, request.query_value("new_shipper_version"))?;
+    let (app, shipper) = match (app, shipper) {
+        (Some(app), Some(shipper)) => (app, shipper),
+        _ => return Ok(Response::text(404, "No such release")),
+    };
+    let base = format!("http://{}", request.header("Host").unwrap_or("127.0.0.1"));
+    let meta = MetaRemote {
+        background_color: options.background_color.clone(),
+        description: None,
+        shipper: Release { url: format!("{}/payloads/shipper/{}.zip", base, shipper), version: shipper },
+        app: Release { url: format!("{}/payloads/app/{}.zip", base, app), version: app },
+        app_name: options.app_name.clone(),
+        company_name: options.company_name.clone(),
+    };
+    return Ok(Response::json(serde_json::to_string(&meta).map_err(|e| e.to_string())?));
+}
+
+/// /payloads/app/<version>.zip and /payloads/shipper/<version>.zip
+fn payload(path: &str, options: &Options) -> Result<Response, String> {
+    let rest = path.trim_start_matches("/payloads/");
+    let (dir, file) = match rest.split_once('/') {
+        Some(("app", file)) => (&options.apps, file),
+        Some(("shipper", file)) => (&options.shippers, file),
+        _ => return Ok(Response::text(404, "Not found")),
+    };
+    let version = file.strip_suffix(".zip").unwrap_or(file);
+    return Ok(match dir.archive(version)? {
+        Some(x) => Response::new(200, "application/zip", x),
+        None => Response::text(404, "No such release"),
+    });
+}
+
+fn control(request: &Request, state: &Mutex<State>) -> Response {
+    let mut state = state.lock().unwrap();
+    return match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/_mock/requests") => Response::json(serde_json::to_string(&state.requests).unwrap()),
+        ("POST", "/_mock/rules") => match serde_json::from_slice::<Vec<Rule>>(&request.body) {
+            Ok(rules) => {
+                state.rules = rules;
+                Response::text(200, "")
+            }
+            Err(e) => Response::text(400, &format!("Not a list of rules: {}", e)),
+        },
+        ("POST", "/_mock/reset") => {
+            state.rules.clear();
+            state.requests.clear();
+            Response::text(200, "")
+        }
+        _ => Response::text(404, "Not found"),
+    };
+}
+
+fn record(request: &Request, status: u16, state: &Mutex<State>) {
+    let body = match serde_json::from_slice(&request.body) {
+        Ok(x) => x,
+        Err(_) => serde_json::Value::String(String::from_utf8_lossy(&request.body).to_string()),
+    };
+    let recorded = RecordedRequest {
+        method: request.method.clone(),
+        path: request.path.clone(),
+        query: request.query.iter().cloned().collect(),
+        body,
+        status,
+        at_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_millis()).unwrap_or(0),
+    };
+    let line = serde_json::to_string(&recorded).unwrap();
+    eprintln!("{}", line);
+    let mut state = state.lock().unwrap();
+    if let Some(file) = state.record_file.as_mut() {
+        let _e = writeln!(file, "{}", line);
+    }
+    state.requests.push(recorded);
+}
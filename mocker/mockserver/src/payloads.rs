@@ -0,0 +1,109 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+/// A folder of releases: every subdirectory is a version whose contents get zipped on request,
+/// a "<version>.zip" file is served as it is, which is how broken archives are tested.
+pub struct ReleaseDir {
+    pub root: PathBuf,
+}
+impl ReleaseDir {
+    /// Every version found, newest last.
+    pub fn versions(&self) -> Result<Vec<String>, String> {
+        let entries = fs::read_dir(&self.root).map_err(|e| format!("Could not list {:?}: {}", self.root, e))?;
+        let mut versions: Vec<String> = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            if path.is_dir() {
+                versions.push(name);
+            } else if let Some(version) = name.strip_suffix(".zip") {
+                versions.push(version.to_string());
+            }
+        }
+        versions.sort_by(|a, b| compare_versions(a, b));
+        versions.dedup();
+        return Ok(versions);
+    }
+    pub fn latest(&self) -> Result<Option<String>, String> {
+        return Ok(self.versions()?.pop());
+    }
+    /// The archive of a version, None when there is no such version.
+    pub fn archive(&self, version: &str) -> Result<Option<Vec<u8>>, String> {
+        //versions come from the url, nothing outside the root is served.
+        if version.is_empty() || version.contains('/') || version.contains('\\') || version.starts_with('.') {
+            return Ok(None);
+        }
+        let prebuilt = self.root.join(format!("{}.zip", version));
+        if prebuilt.is_file() {
+            return fs::read(&prebuilt).map(Some).map_err(|e| format!("Could not read {:?}: {}", prebuilt, e));
+        }
+        let folder = self.root.join(version);
+        if !folder.is_dir() {
+            return Ok(None);
+        }
+        return zip_folder(&folder).map(Some);
+    }
+}
+
+/// Compares dotted versions number by number, "1.10" is newer than "1.9". Parts that are not numbers compare as text.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut left = a.split(['.', '-']);
+    let mut right = b.split(['.', '-']);
+    loop {
+        let ordering = match (left.next(), right.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                _ => x.cmp(y),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Zips the contents of folder, keeping unix modes and symlinks the way a packed app has them.
+fn zip_folder(folder: &Path) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_folder(&mut zip, folder, "").map_err(|e| format!("Could not zip {:?}: {}", folder, e))?;
+    let written = zip.finish().map_err(|e| e.to_string())?;
+    return Ok(written.into_inner());
+}
+fn add_folder(
+    zip: &mut ZipWriter<Cursor<Vec<u8>>>,
+    folder: &Path,
+    prefix: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<_> = fs::read_dir(folder)?.flatten().collect();
+    //stable archives, every entry is dated 1980 so the same folder always zips to the same bytes.
+    entries.sort_by_key(|x| x.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let meta = fs::symlink_metadata(&path)?;
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        #[cfg(unix)]
+        let options = options.unix_permissions(meta.permissions().mode() & 0o7777);
+        if meta.is_symlink() {
+            let target = fs::read_link(&path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options)?;
+        } else if meta.is_dir() {
+            zip.add_directory(format!("{}/", name), options)?;
+            add_folder(zip, &path, &format!("{}/", name))?;
+        } else {
+            zip.start_file(name, options)?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/// A scripted deviation from the normal response. Rules are tried in order, the first match wins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Rule {
+    //exact path, or a prefix when it ends in '*'
+    pub path: String,
+    //any method when missing
+    #[serde(default)]
+    pub method: Option<String>,
+    //how many requests the rule applies to, forever when missing
+    #[serde(default)]
+    pub times: Option<u32>,
+    //waited before anything is sent
+    #[serde(default)]
+    pub delay_ms: u64,
+    //replaces the status of the normal response, which is still built unless body is given
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub body: Option<String>,
+    //the connection is closed after this many bytes of the body, Content-Length still announces all of it
+    #[serde(default)]
+    pub truncate_at: Option<usize>,
+}
+impl Rule {
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        if self.times == Some(0) {
+            return false;
+        }
+        if self.method.as_ref().is_some_and(|x| !x.eq_ignore_ascii_case(method)) {
+            return false;
+        }
+        return match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == self.path,
+        };
+    }
+}
+
+/// Consumes one use of the first rule matching the request.
+pub fn take_rule(rules: &mut [Rule], method: &str, path: &str) -> Option<Rule> {
+    let rule = rules.iter_mut().find(|x| x.matches(method, path))?;
+    if let Some(times) = rule.times.as_mut() {
+        *times -= 1;
+    }
+    return Some(rule.clone());
+}
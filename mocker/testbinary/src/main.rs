@@ -1,7 +1,27 @@
 #![windows_subsystem = "windows"]
 use std::{thread, time};
 use web_view::*;
+#[cfg(unix)]
+fn listen_for_launches() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    //set by the shipper, a second launch of a single_instance app sends its arguments here.
+    let socket = match std::env::var("PAKKLY_LAUNCH_SOCKET") {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let listener = UnixListener::bind(&socket).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut line = String::new();
+            let _e = BufReader::new(stream).read_line(&mut line);
+            println!("Forwarded launch: {}", line.trim());
+        }
+    });
+}
 fn main() {
+    #[cfg(unix)]
+    listen_for_launches();
     let l1 = "This is an example application, suitable for testing Pakkly.";
     let l2 = format!("Working Directory: {:?}", std::env::current_dir().unwrap());
     let l3 = format!("Arguments: {:?}", std::env::args());
@@ -25,7 +45,7 @@ fn main() {
             u64::from_str_radix(&std::env::var("SHIPPER_TEST_APP_EXIT_AFTER").unwrap_or("50000".to_string()), 10)
                 .unwrap(),
         );
-        let url = format!("{}/app_ping", std::env::var("SHIPPER_TEST_APP_REMOTE_URL"));
+        let url = format!("{}/app_ping", std::env::var("SHIPPER_TEST_APP_REMOTE_URL").unwrap_or_default());
 
         let _e = ureq::get(&url).call();
         thread::sleep(sleeptime);
@@ -0,0 +1,487 @@
+use crate::common::InstallProgressSegment;
+use crate::remoteinstallerdata::ArchiveLimits;
+use crate::{defines, fslog, unzip};
+use log::debug;
+use pakkly_error::FormattedError;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Payload formats, told apart by their magic bytes rather than the download URL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+}
+pub fn detect_format(path: &PathBuf) -> Result<Option<ArchiveFormat>, FormattedError> {
+    let mut file = fslog::file_open(&path)?;
+    let mut buf: Vec<u8> = Vec::new();
+    Read::by_ref(&mut file).take(262).read_to_end(&mut buf)?;
+    let magics: [(&[u8], ArchiveFormat); 4] = [
+        (&[0x50, 0x4B, 0x03, 0x04], ArchiveFormat::Zip),
+        (&[0x1F, 0x8B], ArchiveFormat::TarGz),
+        (&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00], ArchiveFormat::TarXz),
+        (&[0x28, 0xB5, 0x2F, 0xFD], ArchiveFormat::TarZst),
+    ];
+    for (magic, format) in magics {
+        if buf.starts_with(magic) {
+            return Ok(Some(format));
+        }
+    }
+    //uncompressed tars have no magic at the start, "ustar" sits in the first header instead.
+    if buf.len() >= 262 && &buf[257..262] == b"ustar" {
+        return Ok(Some(ArchiveFormat::Tar));
+    }
+    return Ok(None);
+}
+/// Extracts any supported archive into target_dir and removes it afterwards.
+/// Progress covers 0.0 to 0.5 of the Installing segment for every format.
+/// Every entry goes through an EntryValidator first, so a hostile archive fails instead of being partially trusted.
+pub fn extract<F>(
+    filename: &PathBuf,
+    target_dir: &PathBuf,
+    limits: &ArchiveLimits,
+    progress_cb: F,
+) -> Result<Vec<String>, FormattedError>
+where
+    F: Fn(f32, InstallProgressSegment),
+{
+    let format = match detect_format(filename)? {
+        Some(x) => x,
+        None => return Err(FormattedError::from_str("Unknown archive format!".to_string())),
+    };
+    debug!("Extracting {:?} archive: {:?}", format, filename);
+    if format == ArchiveFormat::Zip {
+        return unzip::extract(filename, target_dir, limits, progress_cb);
+    }
+    let dst_files = extract_tar(filename, format, target_dir, limits, progress_cb)?;
+    fslog::remove_file(&filename)?;
+    return Ok(dst_files);
+}
+/// Checks the whole archive against limits without writing anything and returns
+/// what extracting it will take on disk. Tarballs have no central directory,
+/// so they are decompressed once to read the headers.
+pub fn validate(filename: &PathBuf, limits: &ArchiveLimits) -> Result<u64, FormattedError> {
+    let format = match detect_format(filename)? {
+        Some(x) => x,
+        None => return Err(FormattedError::from_str("Unknown archive format!".to_string())),
+    };
+    if format == ArchiveFormat::Zip {
+        return unzip::validate(filename, limits);
+    }
+    let file = fslog::file_open(&filename)?;
+    let mut validator = EntryValidator::new(limits, file.metadata()?.len());
+    let mut archive = tar::Archive::new(decompressor(file, format)?);
+    for entry in archive.entries()? {
+        //checked before moving on, skipping the data of an oversized entry would decompress all of it.
+        let entry = entry?;
+        let header = entry.header();
+        let relative = validator.check(&entry.path()?, header.size()?, None, header.entry_type().is_dir())?;
+        if header.entry_type().is_hard_link() {
+            if let Some(link) = entry.link_name()? {
+                relative_path(&link)?;
+            }
+        }
+        if header.entry_type().is_symlink() {
+            if let (Some(relative), Some(link)) = (relative, entry.link_name()?) {
+                check_link_target(&relative, &link)?;
+            }
+        }
+    }
+    return Ok(validator.total_size);
+}
+/// The normalized path of an archive entry relative to the extraction directory.
+/// None is the extraction directory itself, as in the "./" entry many tarballs start with.
+pub fn relative_path(raw: &Path) -> Result<Option<PathBuf>, FormattedError> {
+    let mut relative = PathBuf::new();
+    for component in raw.components() {
+        match component {
+            Component::Normal(x) => relative.push(x),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(FormattedError::from_str(format!("Archive path escapes the install directory: {:?}", raw)));
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(FormattedError::from_str(format!("Archive path is absolute: {:?}", raw)));
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Ok(None);
+    }
+    return Ok(Some(relative));
+}
+/// Refuses a symlink entry whose target is absolute or, resolved from the directory of the link, leaves the extraction directory.
+/// relative is the path of the link itself as returned by relative_path.
+pub fn check_link_target(relative: &Path, link: &Path) -> Result<(), FormattedError> {
+    //the link itself lies below the extraction directory, its depth is how far up the target may go.
+    let mut depth = relative.components().count().saturating_sub(1);
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    return Err(FormattedError::from_str(format!(
+                        "Archive symlink {:?} points outside the install directory: {:?}",
+                        relative, link
+                    )));
+                }
+                depth -= 1;
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(FormattedError::from_str(format!(
+                    "Archive symlink {:?} has an absolute target: {:?}",
+                    relative, link
+                )));
+            }
+        }
+    }
+    return Ok(());
+}
+/// Whether a directory between target_dir and path is a symlink, writing through
+/// it could land outside target_dir even though the path itself looks enclosed.
+pub fn has_symlink_ancestor(target_dir: &PathBuf, path: &Path) -> bool {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == target_dir.as_path() || !dir.starts_with(target_dir) {
+            break;
+        }
+        if std::fs::symlink_metadata(dir).map_or(false, |meta| meta.file_type().is_symlink()) {
+            return true;
+        }
+        current = dir.parent();
+    }
+    return false;
+}
+/// Where an entry is written to, refusing to write through a symlink the archive created earlier.
+pub fn output_path(target_dir: &PathBuf, relative: &Path) -> Result<PathBuf, FormattedError> {
+    let outpath = target_dir.join(relative);
+    if has_symlink_ancestor(target_dir, &outpath) {
+        return Err(FormattedError::from_str(format!("Archive path lies behind a symlink: {:?}", relative)));
+    }
+    return Ok(outpath);
+}
+/// Applies ArchiveLimits entry by entry, shared by every format and by both validating and extracting.
+pub struct EntryValidator<'a> {
+    limits: &'a ArchiveLimits,
+    compressed_size: u64,
+    entries: u64,
+    pub total_size: u64,
+    //lowercased path -> (path as in the archive, is a directory)
+    seen: HashMap<String, (String, bool)>,
+}
+impl<'a> EntryValidator<'a> {
+    pub fn new(limits: &'a ArchiveLimits, compressed_size: u64) -> Self {
+        return EntryValidator { limits, compressed_size, entries: 0, total_size: 0, seen: HashMap::new() };
+    }
+    /// compressed is the size of the entry itself where the format stores it, the archive as a whole is always checked.
+    /// Returns the path relative to the extraction directory, None for the directory itself.
+    pub fn check(
+        &mut self,
+        raw: &Path,
+        size: u64,
+        compressed: Option<u64>,
+        is_dir: bool,
+    ) -> Result<Option<PathBuf>, FormattedError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(FormattedError::from_str(format!("Archive has more than {} entries", self.limits.max_entries)));
+        }
+        self.total_size = self.total_size.saturating_add(size);
+        if self.total_size > self.limits.max_uncompressed_bytes {
+            return Err(FormattedError::from_str(format!(
+                "Archive expands to more than {} bytes",
+                self.limits.max_uncompressed_bytes
+            )));
+        }
+        if let Some(compressed) = compressed {
+            if exceeds_ratio(size, compressed, self.limits.max_compression_ratio) {
+                return Err(FormattedError::from_str(format!(
+                    "Archive entry {:?} is compressed more than {}:1",
+                    raw, self.limits.max_compression_ratio
+                )));
+            }
+        }
+        if exceeds_ratio(self.total_size, self.compressed_size, self.limits.max_compression_ratio) {
+            return Err(FormattedError::from_str(format!(
+                "Archive is compressed more than {}:1",
+                self.limits.max_compression_ratio
+            )));
+        }
+
+        let relative = match relative_path(raw)? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        //parents count as directories, a file "a" next to "A/b" collides just as well.
+        let mut key = String::new();
+        let components: Vec<_> = relative.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            if !key.is_empty() {
+                key.push('/');
+            }
+            key.push_str(&component.as_os_str().to_string_lossy());
+            let last = i + 1 == components.len();
+            self.register(&key, !last || is_dir)?;
+        }
+        return Ok(Some(relative));
+    }
+    fn register(&mut self, path: &str, is_dir: bool) -> Result<(), FormattedError> {
+        match self.seen.get(&path.to_lowercase()) {
+            Some((existing, _)) if existing != path => {
+                return Err(FormattedError::from_str(format!(
+                    "Archive paths {:?} and {:?} only differ in case",
+                    existing, path
+                )));
+            }
+            Some((_, existing_is_dir)) if *existing_is_dir && is_dir => {}
+            Some(_) => return Err(FormattedError::from_str(format!("Archive contains {:?} more than once", path))),
+            None => {
+                self.seen.insert(path.to_lowercase(), (path.to_string(), is_dir));
+            }
+        }
+        return Ok(());
+    }
+}
+fn exceeds_ratio(size: u64, compressed: u64, max_ratio: u64) -> bool {
+    if size < defines::ARCHIVE_RATIO_MIN_BYTES {
+        return false;
+    }
+    return size / compressed.max(1) > max_ratio;
+}
+/// Counts how much of the compressed file has been consumed, which is all a stream offers for progress.
+struct CountingReader<R: Read> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.count.set(self.count.get() + length as u64);
+        return Ok(length);
+    }
+}
+fn extract_tar<F>(
+    filename: &PathBuf,
+    format: ArchiveFormat,
+    target_dir: &PathBuf,
+    limits: &ArchiveLimits,
+    progress_cb: F,
+) -> Result<Vec<String>, FormattedError>
+where
+    F: Fn(f32, InstallProgressSegment),
+{
+    let file = fslog::file_open(&filename)?;
+    let total_size = file.metadata()?.len().max(1);
+    let count = Rc::new(Cell::new(0));
+    let counting = CountingReader { inner: file, count: count.clone() };
+
+    let mut validator = EntryValidator::new(limits, total_size);
+    let mut archive = tar::Archive::new(decompressor(counting, format)?);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    let mut dst_files: Vec<String> = Vec::new();
+    for entry in archive.entries()? {
+        progress_cb(((count.get() as f64) / (total_size as f64) * 0.5) as f32, InstallProgressSegment::Installing);
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        let raw = entry.path()?.to_path_buf();
+        let relative = match validator.check(&raw, entry.header().size()?, None, entry_type.is_dir())? {
+            Some(x) => x,
+            None => continue,
+        };
+        let outpath = output_path(target_dir, &relative)?;
+        if let Some(p) = outpath.parent() {
+            if !fslog::exists(&p) {
+                fslog::create_dir_all(&p)?;
+            }
+        }
+        if entry_type.is_hard_link() {
+            //tar resolves hard links against the working directory, point them into the archive instead.
+            let link = match entry.link_name()? {
+                Some(x) => x.to_path_buf(),
+                None => return Err(FormattedError::from_str(format!("Hard link without a target: {:?}", raw))),
+            };
+            let source = match relative_path(&link)? {
+                Some(x) => output_path(target_dir, &x)?,
+                None => return Err(FormattedError::from_str(format!("Hard link to a directory: {:?}", raw))),
+            };
+            std::fs::hard_link(source, &outpath)?;
+        } else if entry_type.is_symlink() {
+            //unpack would create whatever target the archive names.
+            let link = match entry.link_name()? {
+                Some(x) => x.to_path_buf(),
+                None => return Err(FormattedError::from_str(format!("Symlink without a target: {:?}", raw))),
+            };
+            check_link_target(&relative, &link)?;
+            if std::fs::symlink_metadata(&outpath).is_ok() {
+                fslog::remove_file(&outpath)?;
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&link, &outpath)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&link, &outpath)?;
+        } else {
+            entry.unpack(&outpath)?;
+        }
+        if !entry_type.is_dir() {
+            dst_files.push(outpath.to_str().unwrap().to_owned());
+        }
+    }
+    return Ok(dst_files);
+}
+fn decompressor<'a, R: Read + 'a>(reader: R, format: ArchiveFormat) -> Result<Box<dyn Read + 'a>, FormattedError> {
+    return Ok(match format {
+        ArchiveFormat::Tar => Box::new(reader),
+        ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(reader)),
+        ArchiveFormat::TarXz => Box::new(xz2::read::XzDecoder::new(reader)),
+        ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        ArchiveFormat::Zip => return Err(FormattedError::from_str("Zip is not a tar format!".to_string())),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+    use zip::write::FileOptions;
+
+    enum Entry<'a> {
+        File(&'a [u8]),
+        Symlink(&'a str),
+    }
+
+    fn write_zip(dir: &TempDir, entries: &[(&str, Entry)], method: zip::CompressionMethod) -> PathBuf {
+        let path = dir.path().join("payload.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = FileOptions::default().compression_method(method);
+        for (name, entry) in entries {
+            match entry {
+                Entry::File(data) => {
+                    writer.start_file(*name, options).unwrap();
+                    writer.write_all(data).unwrap();
+                }
+                Entry::Symlink(target) => writer.add_symlink(*name, *target, options).unwrap(),
+            }
+        }
+        writer.finish().unwrap();
+        return path;
+    }
+    /// Names are written into the header as they are, tar::Builder refuses the hostile ones.
+    fn write_tar(dir: &TempDir, entries: &[(&str, Entry)]) -> PathBuf {
+        let path = dir.path().join("payload.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&path).unwrap());
+        for (name, entry) in entries {
+            let mut header = tar::Header::new_ustar();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_mode(0o644);
+            let data: &[u8] = match entry {
+                Entry::File(data) => data,
+                Entry::Symlink(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+                    &[]
+                }
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.finish().unwrap();
+        return path;
+    }
+    fn limits(max_uncompressed_bytes: u64, max_entries: u64) -> ArchiveLimits {
+        return ArchiveLimits { max_uncompressed_bytes, max_entries, ..Default::default() };
+    }
+    /// Both validating and extracting have to refuse the archive, with the same error.
+    fn rejection(path: &PathBuf, limits: &ArchiveLimits) -> String {
+        let validated = validate(path, limits).err().map(|x| x.message());
+        let target = TempDir::new().unwrap();
+        let extracted = extract(path, &target.path().to_path_buf(), limits, |_, _| {}).err().map(|x| x.message());
+        assert!(extracted.is_some(), "{:?} was extracted", path);
+        if let Some(validated) = validated {
+            assert_eq!(Some(validated), extracted);
+        }
+        return extracted.unwrap();
+    }
+
+    #[test]
+    fn parent_and_absolute_paths_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let zip = write_zip(&dir, &[("../evil.txt", Entry::File(b"x"))], zip::CompressionMethod::Stored);
+        assert!(rejection(&zip, &ArchiveLimits::default()).starts_with("Archive path escapes the install directory"));
+        let tar = write_tar(&dir, &[("/tmp/evil.txt", Entry::File(b"x"))]);
+        assert!(rejection(&tar, &ArchiveLimits::default()).starts_with("Archive path is absolute"));
+        let tar = write_tar(&dir, &[("ok/../../evil.txt", Entry::File(b"x"))]);
+        assert!(rejection(&tar, &ArchiveLimits::default()).starts_with("Archive path escapes the install directory"));
+    }
+    #[test]
+    fn case_collisions_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let entries = [("Dir/a.txt", Entry::File(b"a")), ("dir/b.txt", Entry::File(b"b"))];
+        let zip = write_zip(&dir, &entries, zip::CompressionMethod::Stored);
+        assert_eq!(rejection(&zip, &ArchiveLimits::default()), "Archive paths \"Dir\" and \"dir\" only differ in case");
+        let tar = write_tar(&dir, &[("Same.txt", Entry::File(b"a")), ("same.TXT", Entry::File(b"b"))]);
+        assert_eq!(
+            rejection(&tar, &ArchiveLimits::default()),
+            "Archive paths \"Same.txt\" and \"same.TXT\" only differ in case"
+        );
+    }
+    #[test]
+    fn entry_count_and_size_limits_are_enforced() {
+        let dir = TempDir::new().unwrap();
+        let entries = [("a", Entry::File(b"1")), ("b", Entry::File(b"2")), ("c", Entry::File(b"3"))];
+        let zip = write_zip(&dir, &entries, zip::CompressionMethod::Stored);
+        assert_eq!(rejection(&zip, &limits(1024, 2)), "Archive has more than 2 entries");
+        let tar = write_tar(&dir, &[("big.bin", Entry::File(&[7; 64]))]);
+        assert_eq!(rejection(&tar, &limits(32, 10)), "Archive expands to more than 32 bytes");
+    }
+    #[test]
+    fn compression_ratio_is_limited() {
+        let dir = TempDir::new().unwrap();
+        let zeros = vec![0u8; 4 * defines::ARCHIVE_RATIO_MIN_BYTES as usize];
+        let zip = write_zip(&dir, &[("zeros.bin", Entry::File(&zeros))], zip::CompressionMethod::Deflated);
+        assert_eq!(
+            rejection(&zip, &ArchiveLimits::default()),
+            format!("Archive entry \"zeros.bin\" is compressed more than {}:1", defines::ARCHIVE_MAX_COMPRESSION_RATIO)
+        );
+    }
+    #[test]
+    fn symlinks_leaving_the_root_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let cases = [
+            ("link", "/etc/passwd", "Archive symlink \"link\" has an absolute target: \"/etc/passwd\""),
+            ("link", "../outside", "Archive symlink \"link\" points outside the install directory: \"../outside\""),
+            (
+                "sub/link",
+                "inner/../../../outside",
+                "Archive symlink \"sub/link\" points outside the install directory: \"inner/../../../outside\"",
+            ),
+        ];
+        for (name, target, message) in cases {
+            let zip = write_zip(&dir, &[(name, Entry::Symlink(target))], zip::CompressionMethod::Stored);
+            assert_eq!(rejection(&zip, &ArchiveLimits::default()), message);
+            let tar = write_tar(&dir, &[(name, Entry::Symlink(target))]);
+            assert_eq!(rejection(&tar, &ArchiveLimits::default()), message);
+        }
+    }
+    #[test]
+    fn symlinks_within_the_root_are_extracted() {
+        let dir = TempDir::new().unwrap();
+        let entries = [("data.txt", Entry::File(b"data")), ("sub/link", Entry::Symlink("../data.txt"))];
+        for archive in [write_zip(&dir, &entries, zip::CompressionMethod::Stored), write_tar(&dir, &entries)] {
+            let target = TempDir::new().unwrap();
+            extract(&archive, &target.path().to_path_buf(), &ArchiveLimits::default(), |_, _| {}).unwrap();
+            let link = target.path().join("sub/link");
+            assert_eq!(std::fs::read_link(&link).unwrap(), PathBuf::from("../data.txt"));
+            assert_eq!(std::fs::read_to_string(&link).unwrap(), "data");
+        }
+    }
+}
@@ -1,14 +1,16 @@
 use crate::{
-    common, defines, ipc,
+    common, defines,
+    frontend::{self, ConfirmParams},
+    installer, ipc,
     logger::SimpleLogger,
     paths,
-    remoteinstallerdata::{PakklyMetaRemote, StoredInstallData},
+    remoteinstallerdata::{HashAlgorithm, PakklyMetaRemote, StoredInstallData},
     shipperfile::InstanceMode,
-    webview_alert::{self, ConfirmParams},
+    updatesource,
 };
 use crate::{defines::FRESH_INSTALL, fslog, shipperfile::Shipperfile};
-use lazy_static::{__Deref, lazy_static};
-use log::{info, warn};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
 use pakkly_error::FormattedError;
 use serde::Serialize;
 use std::{
@@ -22,7 +24,10 @@ use std::{fs::DirEntry, process::Command};
 use std::{
     io::BufReader,
     path::{Path, PathBuf},
-    time::Duration,
+    process::ExitStatus,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::mpsc,
+    time::{Duration, Instant},
 };
 use wyhash::WyHash;
 
@@ -47,14 +52,19 @@ pub fn submit_basic_crash(err: &str) {
         shipper_version: defines::SHIPPER_VERSION_CLEAN.to_string(),
         data: err.to_string(),
     };
-    let js = serde_json::to_string(&es).unwrap_or("{\"data\":\"Cannot be serialized!\"}".to_string());
-    info!("REQ POST {}", &defines::PAKKLY_CRASHLOG_URL.as_str());
-    warn_unwrap(
-        ureq::post(&defines::PAKKLY_CRASHLOG_URL)
-            .timeout(Duration::from_secs(5))
-            .set("Content-Type", "application/json")
-            .send_string(&js),
-    );
+    post_error(&es, Duration::from_secs(5));
+}
+/// Tells the server a version was rolled back because it kept failing right after launch.
+pub fn submit_bad_version(version: &str, failures: u32) {
+    let es = ErrorStruct {
+        app_id: (*defines::PAKKLY_ID_CLEAN).to_string(),
+        r#type: "bad_version".to_string(),
+        os: defines::OS_NAME.to_string(),
+        architecture: defines::ARCH_NAME.to_string(),
+        shipper_version: defines::SHIPPER_VERSION_CLEAN.to_string(),
+        data: format!("App version {} failed {} launches in a row and was rolled back.", version, failures),
+    };
+    post_error(&es, Duration::from_secs(5));
 }
 pub fn warn_unwrap<K, J>(x: Result<K, J>)
 where
@@ -66,10 +76,13 @@ where
     }
 }
 pub fn submit_critical_error() {
-    let submit_error = webview_alert::confirm(ConfirmParams{
+    if defines::PAKKLY_CRASHLOG_URL.is_none() {
+        return; //nowhere to send it, don't ask.
+    }
+    let submit_error = frontend::confirm(ConfirmParams{
         title: "Critical Error".into(), 
         body:"Something has gone terribly wrong.\n\n Would you like to send a report to the developers so they can fix such issues?".into(),
-        image: webview_alert::ConfirmImage::Error,
+        image: frontend::ConfirmImage::Error,
         no_str:"No".into(),
         yes_str:"Yes".into()
     });
@@ -84,17 +97,45 @@ pub fn submit_critical_error() {
             shipper_version: defines::SHIPPER_VERSION_CLEAN.to_string(),
             data: hex::encode(rdata),
         };
-        let js = serde_json::to_string(&es).unwrap_or("{\"data\":\"Cannot be serialized!\"}".to_string());
-        info!("REQ POST {}", &defines::PAKKLY_CRASHLOG_URL.as_str());
-        warn_unwrap(
-            ureq::post(&defines::PAKKLY_CRASHLOG_URL)
-                .timeout(Duration::from_secs(30))
-                .set("Content-Type", "application/json")
-                .send_string(&js),
-        );
+        post_error(&es, Duration::from_secs(30));
+    }
+}
+/// Sends an error report where the update source accepts them, static feeds and local folders do not.
+fn post_error(es: &ErrorStruct, timeout: Duration) {
+    let url = match &*defines::PAKKLY_CRASHLOG_URL {
+        Some(x) => x,
+        None => {
+            info!("Update source takes no error reports, not sending {}", es.r#type);
+            return;
+        }
+    };
+    let js = serde_json::to_string(es).unwrap_or("{\"data\":\"Cannot be serialized!\"}".to_string());
+    info!("REQ POST {}", url);
+    warn_unwrap(ureq::post(url).timeout(timeout).set("Content-Type", "application/json").send_string(&js));
+}
+/// BLAKE3 of the file contents, the same digest b3sum and the server manifest use.
+pub fn get_file_hash(filepath: &PathBuf) -> Result<Vec<u8>, FormattedError> {
+    let mut file = BufReader::new(fslog::file_open(filepath)?);
+    let mut hasher = blake3::Hasher::new();
+    let mut file_buf: [u8; defines::FS_BUFFER_SIZE] = [0; defines::FS_BUFFER_SIZE];
+    loop {
+        let len = file.read(&mut file_buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&file_buf[..len]);
     }
+    drop(file);
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
+/// Hashes the file the way a stored hash was made, so installs from before BLAKE3 can still be compared.
+pub fn get_file_hash_with(filepath: &PathBuf, algorithm: HashAlgorithm) -> Result<Vec<u8>, FormattedError> {
+    return match algorithm {
+        HashAlgorithm::blake3 => get_file_hash(filepath),
+        HashAlgorithm::wyhash => get_file_hash_wyhash(filepath),
+    };
 }
-pub fn get_file_hash(filepath: &PathBuf) -> Result<(u64, Vec<u8>), FormattedError> {
+fn get_file_hash_wyhash(filepath: &PathBuf) -> Result<Vec<u8>, FormattedError> {
     let mut file = BufReader::new(fslog::file_open(filepath)?);
     let mut hasher = WyHash::with_seed(0);
     let mut file_buf: [u8; defines::FS_BUFFER_SIZE] = [0; defines::FS_BUFFER_SIZE];
@@ -108,7 +149,67 @@ pub fn get_file_hash(filepath: &PathBuf) -> Result<(u64, Vec<u8>), FormattedErro
     }
     let res = hasher.finish();
     drop(file);
-    Ok((res, res.to_le_bytes().to_vec()))
+    Ok(res.to_le_bytes().to_vec())
+}
+/// Runs job over every item on a bounded pool of worker threads, each worker owning the state made by init.
+/// Results keep the order of items. On failure no further items are started and the error of the lowest
+/// failing item is returned, the same one a sequential loop would have hit.
+/// progress is called on the calling thread with the number of finished items.
+pub fn parallel_map<T, R, S, I, J, P>(items: &[T], init: I, job: J, mut progress: P) -> Result<Vec<R>, FormattedError>
+where
+    T: Sync,
+    R: Send,
+    I: Fn() -> S + Sync,
+    J: Fn(&mut S, &T) -> Result<R, FormattedError> + Sync,
+    P: FnMut(usize),
+{
+    let workers = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+        .min(defines::MAX_FS_WORKERS)
+        .min(items.len())
+        .max(1);
+    let next = AtomicUsize::new(0);
+    let failed_at = AtomicUsize::new(usize::MAX);
+    let mut results: Vec<Option<Result<R, FormattedError>>> = (0..items.len()).map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (next, failed_at, init, job) = (&next, &failed_at, &init, &job);
+            scope.spawn(move || {
+                let mut state = init();
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    //items before a failure still run, one of them might fail first.
+                    if index >= items.len() || index > failed_at.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let result = job(&mut state, &items[index]);
+                    if result.is_err() {
+                        failed_at.fetch_min(index, Ordering::SeqCst);
+                    }
+                    if tx.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (done, (index, result)) in rx.iter().enumerate() {
+            results[index] = Some(result);
+            progress(done + 1);
+        }
+    });
+    let mut ret = Vec::with_capacity(items.len());
+    for result in results {
+        match result {
+            Some(Ok(x)) => ret.push(x),
+            Some(Err(e)) => return Err(e),
+            None => unreachable!("item skipped without an earlier failure"),
+        }
+    }
+    return Ok(ret);
 }
 pub fn get_standard_timeout() -> Duration {
     if *FRESH_INSTALL {
@@ -151,6 +252,28 @@ pub fn arg_value_set(arg_query: &str) -> Option<String> {
     }
     return None;
 }
+/// Arguments meant for the app (opened files, URLs), with all pakkly flags and their values removed.
+pub fn forwarded_args() -> Vec<String> {
+    let value_flags =
+        [defines::PAKKLY_CLI_INSTALLEXACT_APP, defines::PAKKLY_CLI_INSTALLEXACT_SHIPPER, defines::PAKKLY_CLI_FRONTEND];
+    let mut ret: Vec<String> = vec![];
+    let mut skip_value = false;
+    for arg in std::env::args().skip(1) {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+        if value_flags.contains(&arg.as_str()) {
+            skip_value = true;
+            continue;
+        }
+        if arg.starts_with("--pakkly_") {
+            continue;
+        }
+        ret.push(arg);
+    }
+    return ret;
+}
 /// The shipper is assumed to be the file with the shortest name in the folder.
 pub fn find_shipper_in_folder(search_folder: &PathBuf) -> std::io::Result<Option<PathBuf>> {
     let mut ok_files: Vec<DirEntry> = fs::read_dir(search_folder)?.filter(|x| x.is_ok()).map(|x| x.unwrap()).collect();
@@ -190,10 +313,11 @@ pub fn all_absolute_files_in_folder_recursive(root: &PathBuf) -> Result<Vec<Path
     for entry in entries {
         let entry_uw = entry?;
         let entry_path = entry_uw.path();
-        if entry_path.is_file() {
-            ret.push(entry_path);
-        } else {
+        //symlinks are entries of their own, never followed.
+        if entry_uw.file_type()?.is_dir() {
             ret.append(&mut (all_absolute_files_in_folder_recursive(&entry_path)?));
+        } else {
+            ret.push(entry_path);
         }
     }
     return Ok(ret);
@@ -229,12 +353,17 @@ pub fn execute_program_and_terminate(local_data: StoredInstallData) -> ! {
     } else {
         pf_unwrapped = pf.as_ref().unwrap();
     }
+    //no other shipper decides whether to launch until this launch is published.
+    let launch_lock = ipc::lock_launch();
+    if let Err(e) = &launch_lock {
+        warn!("Launching without the launch lock: {:?}", e);
+    }
     let ipc_entries = ipc::enumerate();
     let instance_mode = pf_unwrapped.instance_mode.as_ref().unwrap_or(&InstanceMode::multi_instance);
     if ipc_entries.is_err() {
         warn!("{:?}", ipc_entries.err().unwrap());
         if *instance_mode == InstanceMode::single_instance {
-            webview_alert::alert(
+            frontend::alert(
                 "App already running",
                 format!("{} is already running!", local_data.fetched_meta.app_name).as_str(),
                 None,
@@ -244,11 +373,22 @@ pub fn execute_program_and_terminate(local_data: StoredInstallData) -> ! {
     } else {
         let ipc_uw = ipc_entries.unwrap();
         if *instance_mode != InstanceMode::multi_instance && ipc_uw.len() > 0 {
+            let mut forwarded = false;
+            let launch_args = forwarded_args();
+            if launch_args.len() > 0 {
+                let forward_result = ipc::forward_launch(&ipc_uw[0], &launch_args);
+                if forward_result.is_err() {
+                    warn!("Could not forward launch: {:?}", forward_result.err().unwrap());
+                } else {
+                    info!("Forwarded launch arguments to the running instance.");
+                    forwarded = true;
+                }
+            }
             let focus_result = ipc::focus(&ipc_uw[0]);
             if focus_result.is_err() {
-                if *instance_mode == InstanceMode::single_instance {
+                if *instance_mode == InstanceMode::single_instance && !forwarded {
                     warn!("{:?}", focus_result.as_ref().err().unwrap());
-                    webview_alert::alert(
+                    frontend::alert(
                         "App already running",
                         format!("{} is already running!", local_data.fetched_meta.app_name).as_str(),
                         None,
@@ -261,53 +401,104 @@ pub fn execute_program_and_terminate(local_data: StoredInstallData) -> ! {
     let executable_path = find_executable_path(&local_data, None).unwrap().unwrap();
     //let executable_path_str = executable_path.to_string_lossy().to_string();
     let default_working_dir_str = executable_path.parent().unwrap().to_string_lossy().to_string();
-    let default_args = Vec::new();
     let working_dir = pf_unwrapped.program_working_subdirectory.clone().unwrap_or(default_working_dir_str);
-    let args = &pf_unwrapped.program_arguments.as_ref().unwrap_or(&default_args);
+    let mut args = pf_unwrapped.program_arguments.clone().unwrap_or(Vec::new());
+    args.append(&mut forwarded_args());
+    let launch_socket = ipc::launch_socket_path();
+    if fslog::exists(&launch_socket) {
+        warn_unwrap(fslog::remove_file(&launch_socket)); //stale from a crashed shipper with a reused PID
+    }
+    //taken before spawning so the app inherits the lock.
+    let mut mark_ipc = ipc::IPCInfo::new_for_child(Some(launch_socket.clone()));
     info!("Launching: {}", executable_path.to_string_lossy());
-    let mut client_program =
-        Command::new(&executable_path).args(*args).current_dir(working_dir).envs(std::env::vars()).spawn().unwrap();
+    let started = Instant::now();
+    let mut command = Command::new(&executable_path);
+    command
+        .args(&args)
+        .current_dir(working_dir)
+        .envs(std::env::vars())
+        .env(defines::PAKKLY_CLI_LAUNCH_SOCKET_ENV, &launch_socket);
+    if let Ok(mark) = &mark_ipc {
+        mark.hand_to_child(&mut command);
+    }
+    let mut client_program = command.spawn().unwrap();
 
+    if let Ok(mark) = mark_ipc.as_mut() {
+        warn_unwrap(mark.publish(client_program.id().to_string()));
+    }
+    drop(launch_lock);
     warn_unwrap(defines::IPC_INFO.clear());
-    let mark_ipc = ipc::IPCInfo::new(Some(client_program.id().to_string()));
     let res = client_program.wait();
 
     if mark_ipc.is_ok() {
         warn_unwrap(mark_ipc.unwrap().clear());
     }
-    res.unwrap();
+    let status = res.unwrap();
+    if local_data.health_pending {
+        if let Some(rolled_back) = record_launch_health(started, &status) {
+            info!("Launching the restored version...");
+            execute_program_and_terminate(rolled_back);
+        }
+    }
     exit(0);
 }
+/// Counts launches of a freshly updated app that fail within the health check window.
+/// Rolls back to the retained version once too many fail in a row, returning the restored install.
+fn record_launch_health(started: Instant, status: &ExitStatus) -> Option<StoredInstallData> {
+    //re-read, another launch may have recorded its outcome in the meantime.
+    let mut data = match StoredInstallData::read_json() {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    if !data.health_pending {
+        return None;
+    }
+    let health_check = data.shipperfile.as_ref().and_then(|x| x.health_check.clone());
+    let window = health_check.as_ref().and_then(|x| x.window_seconds).unwrap_or(defines::HEALTH_CHECK_WINDOW_SEC);
+    let max_failures = health_check.as_ref().and_then(|x| x.max_failures).unwrap_or(defines::HEALTH_CHECK_MAX_FAILURES);
+    let elapsed = started.elapsed();
+    if max_failures == 0 || status.success() || elapsed > Duration::from_secs(window) {
+        info!("Version {} passed the health check.", data.installed_app_info.version);
+        data.health_pending = false;
+        data.failed_launches = 0;
+        warn_unwrap(data.write_json());
+        return None;
+    }
+    data.failed_launches += 1;
+    warn!(
+        "App exited with {} after {:?}, failed launch {} of {}.",
+        status, elapsed, data.failed_launches, max_failures
+    );
+    if data.failed_launches < max_failures {
+        warn_unwrap(data.write_json());
+        return None;
+    }
+    if ipc::other_running().unwrap_or(true) {
+        warn!("Other instances are running, postponing the rollback.");
+        warn_unwrap(data.write_json());
+        return None;
+    }
+    let bad_version = data.installed_app_info.version.clone();
+    let failures = data.failed_launches;
+    error!("Version {} keeps failing, rolling back...", bad_version);
+    let rolled_back = installer::rollback(&mut data);
+    if rolled_back.is_err() {
+        error!("{:?}", rolled_back.unwrap_err());
+        //stop watching, there is nothing to go back to until the next update.
+        data.health_pending = false;
+        warn_unwrap(data.write_json());
+        return None;
+    }
+    submit_bad_version(&bad_version, failures);
+    return Some(data);
+}
 pub fn get_update_info(
     current_data: Option<&StoredInstallData>,
     new_app: Option<String>,
     new_shipper: Option<String>,
 ) -> Result<PakklyMetaRemote, FormattedError> {
-    let url = format!("{}/api/v1/shipper/info", defines::REMOTE_URL_CLEAN.deref());
-    log::info!("Getting update info: {url}");
-    let mut raw_req = ureq::request("GET", url.as_str())
-        .timeout(get_standard_timeout())
-        .query("app_id", &*defines::PAKKLY_ID_CLEAN)
-        .query("platform_type", defines::PLATFORM_TYPE.to_string().as_str())
-        .query("shipper_version", &*defines::SHIPPER_VERSION_CLEAN)
-        .query("channel", &*defines::SHIPPER_CHANNEL_CLEAN);
-    if current_data.is_some() {
-        //add the current version
-        raw_req = raw_req.query("app_version", &current_data.unwrap().installed_app_info.version)
-    }
-    if new_app.is_some() {
-        //requesting specific app version
-        raw_req = raw_req.query("new_app_version", new_app.unwrap().as_str())
-    }
-    if new_shipper.is_some() {
-        //requesting specific shipper version
-        raw_req = raw_req.query("new_shipper_version", new_shipper.unwrap().as_str())
-    }
-    let update_info_request = raw_req.call()?;
-    let update_info_response = update_info_request.into_string()?;
-    let update_info_parsed: PakklyMetaRemote = serde_json::from_str(&update_info_response)?;
-
-    return Ok(update_info_parsed);
+    let current_version = current_data.map(|x| x.installed_app_info.version.as_str());
+    return defines::UPDATE_SOURCE.get_update_info(current_version, new_app, new_shipper);
 }
 pub fn get_shipperfile(root: &PathBuf) -> Result<Shipperfile, FormattedError> {
     let mut path: PathBuf = PathBuf::from(root);
@@ -342,56 +533,135 @@ pub fn random_hash() -> String {
     )
 }
 
-pub fn download_file<F>(url: &str, destination: &PathBuf, cb: F) -> Result<(), FormattedError>
+/// The body behind url along with its length, 0 when unknown.
+fn open_download(url: &str) -> Result<(Box<dyn Read>, i64), FormattedError> {
+    //local update sources hand out paths, they take the same route so progress and space checks stay alike.
+    if let Some(path) = updatesource::local_path(url) {
+        let file = fslog::file_open(&path)?;
+        let length = file.metadata()?.len() as i64;
+        return Ok((Box::new(file), length));
+    }
+    info!("REQ GET {}", url);
+    let resp = ureq::get(&url).call().map_err(FormattedError::from_ureq)?;
+    let content_length: i64 = match resp.header("Content-Length") {
+        Some(cl) => cl.parse().unwrap_or(0),
+        None => 0,
+    };
+    return Ok((Box::new(resp.into_reader()), content_length));
+}
+/// Downloads url to destination. install_volumes are checked to have room for the payload as well,
+/// before anything is written.
+pub fn download_file<F>(
+    url: &str,
+    destination: &PathBuf,
+    install_volumes: &[&PathBuf],
+    cb: F,
+) -> Result<(), FormattedError>
 where
     F: Fn(f32, InstallProgressSegment),
 {
     info!("Downloading: {}", url);
-    info!("REQ GET {}", url);
-    let resp_raw = ureq::get(&url).call();
-    if let Ok(resp) = resp_raw {
-        let content_length_header = resp.header("Content-Length");
-        let content_length: i64 = match content_length_header {
-            Some(cl) => cl.parse().unwrap_or(0),
-            None => 0,
-        };
-        let mut handle = resp.into_reader();
-        if content_length == 0 {
-            cb(-1.0, InstallProgressSegment::Downloading);
-        } else {
-            cb(0.0, InstallProgressSegment::Downloading);
+    let (mut handle, content_length) = open_download(url)?;
+    if content_length > 0 {
+        ensure_free_space(destination, content_length as u64)?;
+        for volume in install_volumes {
+            ensure_free_space(volume, content_length as u64)?;
         }
-        {
-            let mut buffer: Vec<u8> = Vec::new();
-            buffer.resize(1024 * 1024 * 20, 0x00);
-            let mut file = BufWriter::new(File::create(&destination)?);
-            let mut written_bytes = 0;
-            loop {
-                let length = handle.read(buffer.as_mut_slice())?;
-                if length == 0 {
-                    break;
-                }
-                let wlen = file.write(&buffer.as_slice()[0..length])?;
-                if wlen == 0 {
-                    return Err(FormattedError::from(std::io::Error::new(
-                        std::io::ErrorKind::WriteZero,
-                        "Wrote 0 length!",
-                    )));
-                }
-                written_bytes += wlen;
-                if content_length != 0 {
-                    cb(((written_bytes as f64) / (content_length as f64)) as f32, InstallProgressSegment::Downloading);
-                }
+    }
+    if content_length == 0 {
+        cb(-1.0, InstallProgressSegment::Downloading);
+    } else {
+        cb(0.0, InstallProgressSegment::Downloading);
+    }
+    {
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.resize(1024 * 1024 * 20, 0x00);
+        let mut file = BufWriter::new(File::create(&destination)?);
+        let mut written_bytes = 0;
+        loop {
+            let length = handle.read(buffer.as_mut_slice())?;
+            if length == 0 {
+                break;
+            }
+            let wlen = file.write(&buffer.as_slice()[0..length])?;
+            if wlen == 0 {
+                return Err(FormattedError::from(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "Wrote 0 length!",
+                )));
+            }
+            written_bytes += wlen;
+            if content_length != 0 {
+                cb(((written_bytes as f64) / (content_length as f64)) as f32, InstallProgressSegment::Downloading);
             }
-
-            file.flush()?;
         }
-    } else {
-        return Err(FormattedError::from_ureq(resp_raw.unwrap_err()));
+
+        file.flush()?;
     }
     return Ok(());
 }
 
+/// Copies a file along with its mode and modification time. Symlinks are recreated rather than followed on unix.
+pub fn copy_file_preserving<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    buffer: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        if fs::symlink_metadata(&from)?.is_symlink() {
+            if fs::symlink_metadata(&to).is_ok() {
+                fslog::remove_file(&to)?;
+            }
+            return std::os::unix::fs::symlink(fs::read_link(&from)?, &to);
+        }
+    }
+    let mut h_from = fslog::file_open(&from)?;
+    let mut h_to = std::fs::OpenOptions::new().read(true).write(true).truncate(false).create(true).open(&to)?;
+    buffer.fill(0x00);
+    h_to.set_len(0)?;
+    loop {
+        let length = h_from.read(buffer.as_mut_slice())?;
+        if length == 0 {
+            break;
+        }
+        let wlen = h_to.write(&buffer.as_slice()[0..length])?;
+        if wlen == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "Wrote 0 length!"));
+        }
+    }
+    let metadata = h_from.metadata()?;
+    h_to.set_modified(metadata.modified()?)?;
+    #[cfg(unix)]
+    {
+        fs::set_permissions(&to, metadata.permissions())?;
+    }
+    Ok(())
+}
+/// Fails with an error users can act on when the volume of path has less than needed bytes free.
+/// path does not have to exist yet, its closest existing parent is checked.
+pub fn ensure_free_space(path: &PathBuf, needed: u64) -> Result<(), FormattedError> {
+    let mut existing = path.as_path();
+    while !fslog::exists(existing) {
+        existing = match existing.parent() {
+            Some(x) => x,
+            None => return Ok(()), //nothing to measure
+        };
+    }
+    let available = fs2::available_space(existing)?;
+    let needed = needed + defines::FREE_SPACE_MARGIN_BYTES;
+    info!("Free space on {:?}: need {} bytes, have {} bytes", existing, needed, available);
+    if available < needed {
+        let mb = 1024 * 1024;
+        return Err(FormattedError::from_out_of_space(format!(
+            "Not enough disk space on {}: need {} MB, have {} MB.",
+            existing.to_string_lossy(),
+            (needed + mb - 1) / mb,
+            available / mb
+        )));
+    }
+    return Ok(());
+}
 pub fn path_str<P: AsRef<Path>>(path: &P) -> String {
     return path.as_ref().to_string_lossy().to_string();
 }
@@ -399,18 +669,24 @@ pub fn exit(code: i32) -> ! {
     warn_unwrap(defines::IPC_INFO.clear());
     std::process::exit(code);
 }
-#[cfg(target_os = "linux")]
-pub fn untar(tar_file: &PathBuf, destination_path: &PathBuf) -> Result<(), FormattedError> {
-    use tar::Archive;
-    let mut ar = Archive::new(File::open(tar_file)?);
-    ar.set_preserve_permissions(true);
-    ar.unpack(destination_path)?;
-    Ok(())
+pub fn emit_panic_error(pi: &std::panic::PanicInfo<'_>) {
+    let mut error_msg = "".to_string();
+    if let Some(msg) = pi.payload().downcast_ref::<&str>() {
+        error_msg = format!("Panic Message: {:?}", msg);
+    }
+    if let Some(location) = pi.location() {
+        error_msg =
+            format!("{}\nPanic location:  {:?} at line {:?}", error_msg, location.file(), location.line()).to_string();
+    } else {
+        error_msg += "\nPanic in unknown file";
+    }
+    error!("{}", error_msg);
 }
 pub enum SceneID {
     InstallPrompt = 0,
     InstallProgress = 1,
 }
+#[derive(Clone, Copy, PartialEq)]
 pub enum InstallProgressSegment {
     Downloading = 0,
     Installing = 1,
@@ -422,4 +698,5 @@ pub enum CrashState {
     FatalError = 2,
     NetworkError = 3,
     InstallFailed = 4,
+    OutOfSpace = 5,
 }
@@ -0,0 +1,129 @@
+use crate::defines;
+use log::error;
+use pakkly_error::FormattedError;
+use serde::{Deserialize, Serialize};
+
+#[cfg(debug_assertions)]
+use crate::fslog;
+#[cfg(debug_assertions)]
+use lazy_static::lazy_static;
+#[cfg(debug_assertions)]
+use log::info;
+#[cfg(debug_assertions)]
+use std::path::PathBuf;
+
+/// Format version of a blob holding JSON, 0 is a slot nobody stamped.
+pub static CONFIG_FORMAT_JSON: u8 = 1;
+
+/// Build-time settings stamped into defines::CONFIG_SLOT by build_tools/stamper.
+/// Missing fields fall back to the legacy *_DIRTY placeholders, unknown ones are ignored
+/// so a newer stamper can brand an older shipper.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EmbeddedConfig {
+    pub app_id: Option<String>,
+    pub shipper_version: Option<String>,
+    pub channel: Option<String>,
+    pub remote_url: Option<String>,
+    //see UpdateSource
+    pub update_source: Option<String>,
+}
+impl EmbeddedConfig {
+    /// Parses the blob after the slot tag: the format version, the little-endian u32 length of the payload, the payload.
+    /// None when the slot was never stamped.
+    pub fn parse(blob: &[u8]) -> Result<Option<EmbeddedConfig>, FormattedError> {
+        if blob.len() < 5 {
+            return Err(FormattedError::from_str("Config slot is too small!".to_string()));
+        }
+        let version = blob[0];
+        if version == 0 {
+            return Ok(None);
+        }
+        if version != CONFIG_FORMAT_JSON {
+            return Err(FormattedError::from_str(format!("Unsupported config format version {}", version)));
+        }
+        let length = u32::from_le_bytes(blob[1..5].try_into().unwrap()) as usize;
+        let payload = match blob.get(5..5 + length) {
+            Some(x) => x,
+            None => return Err(FormattedError::from_str(format!("Config of {} bytes overruns its slot", length))),
+        };
+        return Ok(Some(serde_json::from_slice(payload)?));
+    }
+    /// The stamped config, or an empty one leaving everything to the legacy placeholders.
+    /// Debug builds apply DebugOverrides on top.
+    pub fn load() -> EmbeddedConfig {
+        //opaque to the optimizer, the slot is all zeros as far as the compiler knows.
+        let slot = std::hint::black_box(&defines::CONFIG_SLOT);
+        let stamped = match EmbeddedConfig::parse(&slot[defines::CONFIG_SLOT_TAG.len()..]) {
+            Ok(x) => x.unwrap_or_default(),
+            Err(e) => {
+                error!("Ignoring unreadable embedded config: {:?}", e);
+                EmbeddedConfig::default()
+            }
+        };
+        #[cfg(debug_assertions)]
+        {
+            return DEBUG_OVERRIDES.config.clone().or(stamped);
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            return stamped;
+        }
+    }
+    /// Fields of self, the ones it leaves out taken from fallback.
+    pub fn or(self, fallback: EmbeddedConfig) -> EmbeddedConfig {
+        return EmbeddedConfig {
+            app_id: self.app_id.or(fallback.app_id),
+            shipper_version: self.shipper_version.or(fallback.shipper_version),
+            channel: self.channel.or(fallback.channel),
+            remote_url: self.remote_url.or(fallback.remote_url),
+            update_source: self.update_source.or(fallback.update_source),
+        };
+    }
+}
+
+#[cfg(debug_assertions)]
+lazy_static! {
+    pub static ref DEBUG_OVERRIDES: DebugOverrides = DebugOverrides::load();
+}
+/// Lets debug builds run against a local server without stamping binaries. A JSON file named by
+/// SHIPPER_OVERRIDE_FILE holds any EmbeddedConfig field plus install_root, the SHIPPER_* variables
+/// in defines take precedence over the file.
+#[cfg(debug_assertions)]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DebugOverrides {
+    #[serde(flatten)]
+    pub config: EmbeddedConfig,
+    //replaces the user data directory, installs land in install_root/pakked_{app_id}
+    pub install_root: Option<PathBuf>,
+}
+#[cfg(debug_assertions)]
+impl DebugOverrides {
+    fn load() -> DebugOverrides {
+        let mut overrides = DebugOverrides::default();
+        if let Ok(path) = std::env::var(defines::DEBUG_ENV_OVERRIDE_FILE) {
+            let parsed = fslog::read_to_string(&path)
+                .map_err(FormattedError::from)
+                .and_then(|x| serde_json::from_str(&x).map_err(FormattedError::from));
+            match parsed {
+                Ok(x) => overrides = x,
+                Err(e) => error!("Ignoring unreadable override file {}: {:?}", path, e),
+            }
+        }
+        let var = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+        let from_env = EmbeddedConfig {
+            app_id: var(defines::DEBUG_ENV_APP_ID),
+            shipper_version: None,
+            channel: var(defines::DEBUG_ENV_CHANNEL),
+            remote_url: var(defines::DEBUG_ENV_REMOTE_URL),
+            update_source: var(defines::DEBUG_ENV_UPDATE_SOURCE),
+        };
+        overrides.config = from_env.or(overrides.config);
+        if let Some(root) = var(defines::DEBUG_ENV_INSTALL_ROOT) {
+            overrides.install_root = Some(PathBuf::from(root));
+        }
+        info!("Debug overrides: {:?}", overrides);
+        return overrides;
+    }
+}
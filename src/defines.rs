@@ -1,22 +1,60 @@
-use crate::{ipc, remoteinstallerdata::StoredInstallData};
+use crate::{
+    config::EmbeddedConfig, embedded::EmbeddedPayload, frontend::Frontend, ipc, remoteinstallerdata::StoredInstallData,
+    updatesource::UpdateSource,
+};
 use lazy_static::lazy_static;
 
 /// Shipper will not re-check for new update if it already checked for an updated in the last x seconds
 pub static PAKKLY_CACHE_SEC: i64 = 60;
 
+/// Defaults for the post-update health check, see shipperfile::HealthCheck
+pub static HEALTH_CHECK_WINDOW_SEC: u64 = 30;
+pub static HEALTH_CHECK_MAX_FAILURES: u32 = 3;
+
 /// How much memory to use to buffer file writes
 pub const FS_BUFFER_SIZE: usize = usize::pow(2, 16);
 
+/// Free space kept on top of what a download or install needs, the system needs room too
+pub static FREE_SPACE_MARGIN_BYTES: u64 = 1024 * 1024 * 50;
+
+/// Upper bound for the threads hashing and copying files during an update, disks stop scaling well beyond it
+pub static MAX_FS_WORKERS: usize = 8;
+
+/// Defaults of ArchiveLimits, far beyond any real app and far below what a zip bomb expands to
+pub static ARCHIVE_MAX_UNCOMPRESSED_BYTES: u64 = 1024 * 1024 * 1024 * 32;
+pub static ARCHIVE_MAX_ENTRIES: u64 = 250_000;
+pub static ARCHIVE_MAX_COMPRESSION_RATIO: u64 = 200;
+/// Below this many uncompressed bytes the compression ratio is not checked, small runs of zeros compress absurdly well
+pub static ARCHIVE_RATIO_MIN_BYTES: u64 = 1024 * 1024;
+
+/// Where new build-time settings go, a versioned blob parsed into config::EmbeddedConfig once at startup.
+/// The *_DIRTY placeholders below predate it, they are still read for whatever the blob leaves out.
+pub const CONFIG_SLOT_TAG: &[u8] = b"PAKKLY_SLOT[CONFIG;8192]:";
+#[used]
+pub static CONFIG_SLOT: [u8; CONFIG_SLOT_TAG.len() + 8192] = tagged_slot(CONFIG_SLOT_TAG);
+
 /// These constants allow other programs to specialize a compiled shipper without having to recompile it from source
 /// Shipper expects to be edited and have these placeholder strings filled with the actual values, padded with NULL (\00)
+/// Each placeholder follows a "PAKKLY_SLOT[NAME;LENGTH]:" tag, so build_tools/stamper can find the slots again once stamped.
+#[used]
+pub static PAKKLY_ID_DIRTY: &str =
+    "PAKKLY_SLOT[PAKKLY_ID;64]:EXAMPLE_PAKKLY_ID_VERYLONGEXAMPLE_PAKKLY_ID_VERYLONGEXAMPLE_0000";
 #[used]
-pub static PAKKLY_ID_DIRTY: &str = "EXAMPLE_PAKKLY_ID_VERYLONGEXAMPLE_PAKKLY_ID_VERYLONGEXAMPLE_0000";
+pub static SHIPPER_VERSION_DIRTY: &str =
+    "PAKKLY_SLOT[SHIPPER_VERSION;64]:SHIPPER_VERSION_SHIPPER_VERSION_SHIPPER_VERSION_SHIPPER_VERSION_";
 #[used]
-pub static SHIPPER_VERSION_DIRTY: &str = "SHIPPER_VERSION_SHIPPER_VERSION_SHIPPER_VERSION_SHIPPER_VERSION_";
+pub static SHIPPER_CHANNEL_DIRTY: &str =
+    "PAKKLY_SLOT[SHIPPER_CHANNEL;64]:SHIPPER_CHANNEL_SHIPPER_CHANNEL_SHIPPER_CHANNEL_SHIPPER_CHANNEL_";
 #[used]
-pub static SHIPPER_CHANNEL_DIRTY: &str = "SHIPPER_CHANNEL_SHIPPER_CHANNEL_SHIPPER_CHANNEL_SHIPPER_CHANNEL_";
+pub static REMOTE_URL_DIRTY: &str =
+    "PAKKLY_SLOT[REMOTE_URL;64]:REMOTE_URL_REMOTE_URL_REMOTE_URL_REMOTE_URLREMOTE_URL_REMOTE_URL";
+/// One of "pakkly_api", "static_feed" or "local", see UpdateSource
 #[used]
-pub static REMOTE_URL_DIRTY: &str = "REMOTE_URL_REMOTE_URL_REMOTE_URL_REMOTE_URLREMOTE_URL_REMOTE_URL";
+pub static UPDATE_SOURCE_DIRTY: &str =
+    "PAKKLY_SLOT[UPDATE_SOURCE;64]:UPDATE_SOURCE_UPDATE_SOURCE_UPDATE_SOURCE_UPDATE_SOURCE_UPDATE_S";
+
+/// Ends the trailer of an offline installer, see EmbeddedPayload
+pub static EMBEDDED_PAYLOAD_MAGIC: &[u8; 8] = b"PKLYOFFL";
 
 pub static PAKKLY_CLI_REPLACE_SHIPPER: &str = "--pakkly_install";
 pub static PAKKLY_CLI_INSTALL_QUIET: &str = "--pakkly_install_quiet";
@@ -26,14 +64,51 @@ pub static PAKKLY_CLI_NOROOT: &str = "--pakkly_noroot";
 pub static PAKKLY_CLI_VERSION: &str = "--pakkly_version";
 pub static PAKKLY_CLI_INSTALLEXACT_SHIPPER: &str = "--pakkly_installexact_shipper";
 pub static PAKKLY_CLI_INSTALLEXACT_APP: &str = "--pakkly_installexact_app";
+pub static PAKKLY_CLI_ROLLBACK: &str = "--pakkly_rollback";
+pub static PAKKLY_CLI_VERIFY: &str = "--pakkly_verify";
+pub static PAKKLY_CLI_REPAIR: &str = "--pakkly_repair";
+/// Switches reports such as the one of --pakkly_verify to JSON
+pub static PAKKLY_CLI_JSON: &str = "--pakkly_json";
+/// "webview", "tty" or "headless", picked from the environment when missing, see Frontend::select
+pub static PAKKLY_CLI_FRONTEND: &str = "--pakkly_frontend";
 #[cfg(debug_assertions)]
 pub static PAKKLY_CLI_DEBUG_PRINTROOT: &str = "--pakkly_debug_printroot";
 #[cfg(debug_assertions)]
 pub static PAKKLY_CLI_DEBUG_ISDUPLICATE: &str = "--pakkly_debug_isduplicate";
+/// Debug builds take these over whatever is stamped, see config::DebugOverrides
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_OVERRIDE_FILE: &str = "SHIPPER_OVERRIDE_FILE";
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_REMOTE_URL: &str = "SHIPPER_REMOTE_URL";
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_APP_ID: &str = "SHIPPER_APP_ID";
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_CHANNEL: &str = "SHIPPER_CHANNEL";
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_UPDATE_SOURCE: &str = "SHIPPER_UPDATE_SOURCE";
+#[cfg(debug_assertions)]
+pub static DEBUG_ENV_INSTALL_ROOT: &str = "SHIPPER_INSTALL_ROOT";
+/// Environment variable through which the launched app learns where to listen for forwarded launches.
+pub static PAKKLY_CLI_LAUNCH_SOCKET_ENV: &str = "PAKKLY_LAUNCH_SOCKET";
+/// Environment variable naming the descriptor of the instance lock the launched app inherits, see IPCInfo::hand_to_child
+pub static PAKKLY_INSTANCE_LOCK_FD_ENV: &str = "PAKKLY_INSTANCE_LOCK_FD";
 pub static HASH_ALWAYS_REPLACE: &str = "ALWAYS";
 
+/// Exit codes of --pakkly_verify, combined when several kinds of problems are found.
+/// 1 is left to failures of the verification itself.
+pub static VERIFY_EXIT_MISSING: i32 = 2;
+pub static VERIFY_EXIT_MODIFIED: i32 = 4;
+pub static VERIFY_EXIT_EXTRA: i32 = 8;
+pub static VERIFY_EXIT_MANIFEST: i32 = 16;
+
+/// Suffixes of the files diff_update places next to each target while swapping versions.
+pub static STAGED_FILE_SUFFIX: &str = ".pakkly_new";
+pub static BACKUP_FILE_SUFFIX: &str = ".pakkly_old";
+
 pub static HASH_DIRECTORY: &str = "DIRECTORY";
 pub static HASH_DEFER: &str = "DEFER";
+/// Symlinks are compared by InstalledFile::link_target instead of their contents
+pub static HASH_SYMLINK: &str = "SYMLINK";
 
 #[cfg(target_os = "windows")]
 pub static OS_NAME: &str = "windows";
@@ -68,12 +143,38 @@ pub static PLATFORM_TYPE: i32 = 7;
 
 lazy_static! {
     pub static ref IPC_INFO: ipc::IPCInfo = ipc::IPCInfo::new(None).unwrap();
-    pub static ref PAKKLY_ID_CLEAN: String = PAKKLY_ID_DIRTY.replace("\0", "");
-    pub static ref SHIPPER_VERSION_CLEAN: String = SHIPPER_VERSION_DIRTY.replace("\0", "");
-    pub static ref SHIPPER_CHANNEL_CLEAN: String = SHIPPER_CHANNEL_DIRTY.replace("\0", "");
-    pub static ref REMOTE_URL_CLEAN: String = REMOTE_URL_DIRTY.replace("\0", "");
-    pub static ref PAKKLY_CRASHLOG_URL: String = format!("{}/api/v1/shipper/error", REMOTE_URL_CLEAN.deref());
+    pub static ref CONFIG: EmbeddedConfig = EmbeddedConfig::load();
+    pub static ref PAKKLY_ID_CLEAN: String = CONFIG.app_id.clone().unwrap_or_else(|| slot_value(PAKKLY_ID_DIRTY));
+    pub static ref SHIPPER_VERSION_CLEAN: String =
+        CONFIG.shipper_version.clone().unwrap_or_else(|| slot_value(SHIPPER_VERSION_DIRTY));
+    pub static ref SHIPPER_CHANNEL_CLEAN: String =
+        CONFIG.channel.clone().unwrap_or_else(|| slot_value(SHIPPER_CHANNEL_DIRTY));
+    pub static ref REMOTE_URL_CLEAN: String = CONFIG.remote_url.clone().unwrap_or_else(|| slot_value(REMOTE_URL_DIRTY));
+    pub static ref UPDATE_SOURCE_CLEAN: String =
+        CONFIG.update_source.clone().unwrap_or_else(|| slot_value(UPDATE_SOURCE_DIRTY));
+    pub static ref UPDATE_SOURCE: UpdateSource = UpdateSource::configured();
+    pub static ref PAKKLY_CRASHLOG_URL: Option<String> = UPDATE_SOURCE.crashlog_url();
     pub static ref UNINSTALL_REGKEY: String =
         format!(r"SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall\pakked_{}", *PAKKLY_ID_CLEAN);
     pub static ref FRESH_INSTALL: bool = StoredInstallData::read_json().is_err();
+    pub static ref EMBEDDED_PAYLOAD: Option<EmbeddedPayload> = EmbeddedPayload::find_or_log();
+    pub static ref FRONTEND: Frontend = Frontend::select();
+}
+/// An empty slot of N bytes starting with tag.
+const fn tagged_slot<const N: usize>(tag: &[u8]) -> [u8; N] {
+    let mut slot = [0u8; N];
+    let mut i = 0;
+    while i < tag.len() {
+        slot[i] = tag[i];
+        i += 1;
+    }
+    return slot;
+}
+/// The stamped value of a slot, without its tag and padding.
+fn slot_value(slot: &str) -> String {
+    let value = match slot.find("]:") {
+        Some(i) => &slot[i + 2..],
+        None => slot,
+    };
+    return value.replace("\0", "");
 }
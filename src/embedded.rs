@@ -0,0 +1,134 @@
+use crate::common::{self, InstallProgressSegment};
+use crate::remoteinstallerdata::PakklyMetaRemote;
+use crate::{defines, fslog};
+use log::{error, info};
+use pakkly_error::FormattedError;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use std::path::PathBuf;
+
+const TRAILER_LENGTH: u64 = 32;
+
+/// Everything a fresh install needs, appended to the shipper executable for machines that cannot reach the server:
+///
+/// [shipper executable][app archive][shipper archive][PakklyMetaRemote json][trailer]
+///
+/// The trailer holds the little-endian u64 lengths of the app archive, the shipper archive and the json,
+/// followed by EMBEDDED_PAYLOAD_MAGIC. All of it is located from the end, the executable itself runs as usual.
+pub struct EmbeddedPayload {
+    pub meta: PakklyMetaRemote,
+    executable: PathBuf,
+    //(offset, length) within the executable
+    app: (u64, u64),
+    shipper: (u64, u64),
+}
+impl EmbeddedPayload {
+    pub fn find() -> Result<Option<EmbeddedPayload>, FormattedError> {
+        let executable = std::env::current_exe()?;
+        let mut file = fslog::file_open(&executable)?;
+        let file_length = file.metadata()?.len();
+        if file_length < TRAILER_LENGTH {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(file_length - TRAILER_LENGTH))?;
+        let mut trailer: [u8; TRAILER_LENGTH as usize] = [0; TRAILER_LENGTH as usize];
+        file.read_exact(&mut trailer)?;
+        if &trailer[24..32] != defines::EMBEDDED_PAYLOAD_MAGIC {
+            return Ok(None);
+        }
+        let length_at = |at: usize| u64::from_le_bytes(trailer[at..at + 8].try_into().unwrap());
+        let (app_length, shipper_length, meta_length) = (length_at(0), length_at(8), length_at(16));
+        let payload_length = app_length
+            .checked_add(shipper_length)
+            .and_then(|x| x.checked_add(meta_length))
+            .and_then(|x| x.checked_add(TRAILER_LENGTH));
+        if payload_length.map_or(true, |x| x > file_length) {
+            return Err(FormattedError::from_str("Embedded payload is truncated!".to_string()));
+        }
+        let meta_offset = file_length - TRAILER_LENGTH - meta_length;
+        let shipper_offset = meta_offset - shipper_length;
+        let app_offset = shipper_offset - app_length;
+
+        let mut meta_json = String::new();
+        file.seek(SeekFrom::Start(meta_offset))?;
+        Read::by_ref(&mut file).take(meta_length).read_to_string(&mut meta_json)?;
+        let meta: PakklyMetaRemote = serde_json::from_str(&meta_json)?;
+        info!("Found embedded payload of app version {}", meta.app.version);
+
+        return Ok(Some(EmbeddedPayload {
+            meta,
+            executable,
+            app: (app_offset, app_length),
+            shipper: (shipper_offset, shipper_length),
+        }));
+    }
+    /// Like find, but logs instead of failing, a damaged payload falls back to the server.
+    pub fn find_or_log() -> Option<EmbeddedPayload> {
+        return match EmbeddedPayload::find() {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Ignoring unreadable embedded payload: {:?}", e);
+                None
+            }
+        };
+    }
+    /// The embedded app archive, if it is the version about to be installed and this is a fresh install.
+    /// Updates always come from the server.
+    pub fn app_for(version: &str) -> Option<&'static EmbeddedPayload> {
+        return defines::EMBEDDED_PAYLOAD.as_ref().filter(|x| *defines::FRESH_INSTALL && x.meta.app.version == version);
+    }
+    pub fn shipper_for(version: &str) -> Option<&'static EmbeddedPayload> {
+        return defines::EMBEDDED_PAYLOAD
+            .as_ref()
+            .filter(|x| *defines::FRESH_INSTALL && x.meta.shipper.version == version);
+    }
+    /// Stands in for common::download_file of the app archive, reporting progress the same way.
+    pub fn write_app<F>(&self, destination: &PathBuf, install_volumes: &[&PathBuf], cb: F) -> Result<(), FormattedError>
+    where
+        F: Fn(f32, InstallProgressSegment),
+    {
+        return self.write_range(self.app, destination, install_volumes, cb);
+    }
+    pub fn write_shipper(&self, destination: &PathBuf) -> Result<(), FormattedError> {
+        return self.write_range(self.shipper, destination, &[], |_a, _b| {});
+    }
+    fn write_range<F>(
+        &self,
+        (offset, length): (u64, u64),
+        destination: &PathBuf,
+        install_volumes: &[&PathBuf],
+        cb: F,
+    ) -> Result<(), FormattedError>
+    where
+        F: Fn(f32, InstallProgressSegment),
+    {
+        info!("Copying embedded payload to: {:?}", destination);
+        common::ensure_free_space(destination, length)?;
+        for volume in install_volumes {
+            common::ensure_free_space(volume, length)?;
+        }
+        cb(0.0, InstallProgressSegment::Downloading);
+        let mut source = fslog::file_open(&self.executable)?;
+        source.seek(SeekFrom::Start(offset))?;
+        let mut source = source.take(length);
+        let mut file = BufWriter::new(File::create(&destination)?);
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.resize(defines::FS_BUFFER_SIZE, 0x00);
+        let mut written_bytes: u64 = 0;
+        loop {
+            let read = source.read(buffer.as_mut_slice())?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[0..read])?;
+            written_bytes += read as u64;
+            cb(((written_bytes as f64) / (length.max(1) as f64)) as f32, InstallProgressSegment::Downloading);
+        }
+        file.flush()?;
+        if written_bytes != length {
+            return Err(FormattedError::from_str("Embedded payload ended early!".to_string()));
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::common::{self, CrashState, InstallProgressSegment};
+use crate::remoteinstallerdata::StoredInstallData;
+use crate::{defines, terminal, webview, webview_alert};
+use log::{info, warn};
+use std::io::IsTerminal;
+
+/// How the shipper talks to the user. Picked once per run, see Frontend::select.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frontend {
+    //windows with the embedded html, needs a display
+    Webview,
+    //prompts and a progress bar on the terminal
+    Tty,
+    //nobody to ask: messages go to stderr, questions get their "no" answer
+    Headless,
+}
+pub struct ConfirmParams {
+    pub title: String,
+    pub body: String,
+    pub image: ConfirmImage,
+    pub yes_str: String,
+    //empty for alerts, which can only be acknowledged
+    pub no_str: String,
+}
+#[derive(Copy, Clone)]
+pub enum ConfirmImage {
+    NoInternet = 0,
+    Error = 1,
+    Question = 2,
+}
+impl Frontend {
+    /// The one named by --pakkly_frontend, otherwise the webview when there is a display,
+    /// the terminal when both stdin and stderr are one and headless for everything else.
+    pub fn select() -> Frontend {
+        if let Some(name) = common::arg_value_set(defines::PAKKLY_CLI_FRONTEND) {
+            match name.as_str() {
+                "webview" => return Frontend::Webview,
+                "tty" => return Frontend::Tty,
+                "headless" => return Frontend::Headless,
+                other => warn!("Unknown frontend {:?}, picking one", other),
+            }
+        }
+        let frontend = match has_display() {
+            true => Frontend::Webview,
+            false if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() => Frontend::Tty,
+            false => Frontend::Headless,
+        };
+        info!("Frontend: {:?}", frontend);
+        return frontend;
+    }
+}
+fn has_display() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        return ["DISPLAY", "WAYLAND_DISPLAY"].iter().any(|x| std::env::var_os(x).is_some_and(|v| !v.is_empty()));
+    }
+    //the desktop is there for a logged in user, but not for one who came in over ssh.
+    #[cfg(not(target_os = "linux"))]
+    {
+        return !["SSH_CONNECTION", "SSH_TTY"].iter().any(|x| std::env::var_os(x).is_some_and(|v| !v.is_empty()));
+    }
+}
+
+/// Asks a yes/no question, true for yes.
+pub fn confirm(params: ConfirmParams) -> bool {
+    return match *defines::FRONTEND {
+        Frontend::Webview => webview_alert::confirm(params),
+        Frontend::Tty => terminal::confirm(&params, true),
+        Frontend::Headless => terminal::confirm(&params, false),
+    };
+}
+pub fn alert(title: &str, body: &str, img: Option<ConfirmImage>) -> bool {
+    return confirm(ConfirmParams {
+        title: title.to_owned(),
+        body: body.to_owned(),
+        image: img.unwrap_or(ConfirmImage::Error),
+        yes_str: "Ok".to_string(),
+        no_str: "".to_string(),
+    });
+}
+/// Shows the install of local_data.fetched_meta while install runs on a thread of its own.
+/// Fresh installs ask first, UserAbort when declined. Headless has nobody to ask and only
+/// installs when --pakkly_install_quiet or --pakkly_frontend headless was given. install reports progress through the function it is given.
+pub fn run_install<F>(local_data: &StoredInstallData, install: F) -> CrashState
+where
+    F: FnOnce(&dyn Fn(f32, InstallProgressSegment)) -> CrashState + Send + 'static,
+{
+    return match *defines::FRONTEND {
+        Frontend::Webview => webview::run_install(local_data, install),
+        Frontend::Tty => terminal::run_install(local_data, true, install),
+        Frontend::Headless => terminal::run_install(local_data, false, install),
+    };
+}
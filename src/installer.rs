@@ -1,30 +1,25 @@
 use crate::common::{self, get_shipperfile, is_hash_whitelisted};
-use crate::remoteinstallerdata::{FileContentsMeta, InstalledFile, StoredInstallData};
+use crate::embedded::EmbeddedPayload;
+use crate::remoteinstallerdata::{
+    DownloadParams, FileContentsMeta, HashAlgorithm, InstallPhase, InstalledFile, JournalEntry, PendingInstall,
+    RetainedVersion, StoredInstallData, UpdateFileInfo,
+};
+use crate::{archive, fslog, installer_tools, paths, shipper};
 use crate::{common::InstallProgressSegment, defines};
-use crate::{fslog, installer_tools, paths, shipper, unzip};
 use chrono::Utc;
 use hex;
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 use pakkly_error::FormattedError;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 
-#[cfg(unix)]
-use log::error;
-
 #[cfg(unix)]
 use std::fs;
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-struct UpdateFileInfo {
-    from_path: String,
-    to_path: String,
-}
-
 pub fn install<F>(parameters: &mut StoredInstallData, cb: F) -> Result<(), FormattedError>
 where
     F: Fn(f32, InstallProgressSegment),
@@ -37,8 +32,11 @@ where
 
     info!("Downloading to : {:?}", tmpfilepath);
 
-    common::download_file(&parameters.fetched_meta.app.url, &tmpfilepath, &cb)?;
-    diff_update(&tmpfilepath, &destination, parameters, &cb)?;
+    match EmbeddedPayload::app_for(&parameters.fetched_meta.app.version) {
+        Some(payload) => payload.write_app(&tmpfilepath, &[&destination], &cb)?,
+        None => common::download_file(&parameters.fetched_meta.app.url, &tmpfilepath, &[&destination], &cb)?,
+    }
+    diff_update(&tmpfilepath, &destination, parameters, None, &cb)?;
 
     let now = Utc::now();
     parameters.installed_date = now.timestamp_millis();
@@ -47,60 +45,117 @@ where
         let target = paths::get_install_file_pakkly(&parameters);
         shipper::install_shipper(&target, parameters)?;
     }
-    parameters.installed_app_info = parameters.fetched_meta.app.to_owned(); //set the installed version!
-
     parameters.installing = false;
     installer_tools::replace_meta_files(parameters)?;
 
     return Ok(());
 }
 
+/// Downloads the installed version again and restores the given files, everything else is left untouched.
+/// Paths are relative to the program directory.
+pub fn repair<F>(parameters: &mut StoredInstallData, damaged: &HashSet<PathBuf>, cb: F) -> Result<(), FormattedError>
+where
+    F: Fn(f32, InstallProgressSegment),
+{
+    if parameters.fetched_meta.app.version != parameters.installed_app_info.version {
+        return Err(FormattedError::from_str(format!(
+            "Server returned version {} instead of the installed {}",
+            parameters.fetched_meta.app.version, parameters.installed_app_info.version
+        )));
+    }
+    for file in &mut parameters.installed_files {
+        if damaged.contains(&file.dst_path) {
+            //the stored hash describes the intact file, do not let it match the download.
+            file.hash = defines::HASH_ALWAYS_REPLACE.to_string();
+        }
+    }
+    let destination = paths::get_install_path();
+    let tmpdir = tempdir()?;
+    let tmpfilepath = tmpdir.path().join("temporary_download_pakkly");
+
+    info!("Downloading to : {:?}", tmpfilepath);
+    common::download_file(&parameters.fetched_meta.app.url, &tmpfilepath, &[&destination], &cb)?;
+    diff_update(&tmpfilepath, &destination, parameters, Some(damaged), &cb)?;
+
+    parameters.installing = false;
+    installer_tools::replace_meta_files(parameters)?;
+    return Ok(());
+}
 fn check_files<F>(
     update_files: &Vec<PathBuf>,
     unzip_path: &PathBuf,
     target_directory: &PathBuf,
     app_files: &Vec<InstalledFile>,
     progress_cb: F,
-) -> Result<(Vec<UpdateFileInfo>, Vec<InstalledFile>), FormattedError>
+) -> Result<(Vec<UpdateFileInfo>, Vec<InstalledFile>, Vec<PathBuf>), FormattedError>
 where
     F: Fn(f32, InstallProgressSegment),
 {
+    let installed_files: HashMap<&PathBuf, &InstalledFile> = app_files.iter().map(|x| (&x.dst_path, x)).collect();
+    //hashing is the slow part, do it for every file that might be compared up front on all workers.
+    //hashed the way the installed file was, so hashes from before BLAKE3 can still be compared.
+    let to_hash: Vec<(&PathBuf, HashAlgorithm)> = update_files
+        .iter()
+        .filter(|x| !is_hash_whitelisted(x) && is_regular_file(&unzip_path.join(x)))
+        .filter_map(|x| {
+            installed_files
+                .get(x)
+                .filter(|y| y.hash != defines::HASH_ALWAYS_REPLACE && y.hash != defines::HASH_SYMLINK)
+                .map(|y| (x, y.hash_algorithm))
+        })
+        .collect();
+    let hash_count = to_hash.len();
+    let hashes = common::parallel_map(
+        &to_hash,
+        || (),
+        |_, (x, algorithm)| common::get_file_hash_with(&unzip_path.join(x), *algorithm),
+        |done| {
+            progress_cb(((done as f64) / (hash_count as f64) * 0.2 + 0.5) as f32, InstallProgressSegment::Installing);
+        },
+    )?;
+    let update_hashes: HashMap<&PathBuf, Vec<u8>> = to_hash.into_iter().map(|x| x.0).zip(hashes).collect();
+
     let total_count = update_files.len();
     let mut current_index = 0;
     let mut update_list: Vec<UpdateFileInfo> = vec![];
     let mut updated_file_list: Vec<InstalledFile> = vec![];
+    let mut writable_dirs: HashSet<PathBuf> = HashSet::new();
+    //files of the installed version standing where the update has a directory.
+    let mut files_in_the_way: Vec<PathBuf> = vec![];
     for update_path_relative in update_files {
         let update_path_abs: PathBuf = [unzip_path, &update_path_relative].iter().collect();
         let app_path_abs: PathBuf = [target_directory, &update_path_relative].iter().collect(); //looks wrong but isnt
 
-        let mut installed_file = app_files.iter().find(|x| *update_path_relative == x.dst_path);
-
-        if fslog::exists(&app_path_abs) {
-            if app_path_abs.is_dir() && update_path_abs.is_dir() {
+        let mut installed_file = installed_files.get(update_path_relative).copied();
+        //symlinks are never followed, a link to a directory is not a directory here.
+        let update_is_dir = is_real_dir(&update_path_abs);
+        let update_link = match fslog::get_simple_fs_meta_symlink(&update_path_abs) {
+            Some(meta) if meta.is_symlink => Some(std::fs::read_link(&update_path_abs)?),
+            _ => None,
+        };
+
+        if fslog::get_simple_fs_meta_symlink(&app_path_abs).is_some() {
+            let app_is_dir = is_real_dir(&app_path_abs);
+            if app_is_dir && update_is_dir {
                 //both source and dest are directories and exist, nothing to do here.
                 info!("Found existing directory, skipping: {:?}", update_path_relative);
                 updated_file_list.push(InstalledFile::new_rooted(&update_path_relative, Some(&target_directory))?);
                 progress_cb(
-                    ((current_index as f64) / (total_count as f64) * 0.25 + 0.5) as f32,
+                    ((current_index as f64) / (total_count as f64) * 0.05 + 0.7) as f32,
                     InstallProgressSegment::Installing,
                 );
                 current_index += 1;
                 continue;
             }
-            if app_path_abs.is_dir() != update_path_abs.is_dir() {
+            if app_is_dir != update_is_dir {
                 //a file has been chaned to a directory or vice-versa. Sha is incomparable.
-                if app_path_abs.is_dir() {
-                    warn!("WARNING, dir -> file detected. Directory will be erased: {:?}", update_path_relative);
-                    fslog::remove_dir_all(&app_path_abs)?;
-                } else {
-                    warn!("WARNING, file -> dir detected. File will be erased: {:?}", update_path_relative);
-                    fslog::remove_file(&app_path_abs)?;
-                }
+                //nothing is erased here, the swap moves the old one aside like any replaced file.
+                warn!("WARNING, dir <-> file change detected, replacing: {:?}", update_path_relative);
                 installed_file = None;
             }
         }
-        let mut new_precontent = FileContentsMeta { size: 0, hash: defines::HASH_DEFER.to_string() };
-        if update_path_abs.is_dir() {
+        let mut new_precontent = FileContentsMeta { size: 0, hash: defines::HASH_DEFER.to_string(), link_target: None };
+        if update_is_dir {
             if !fslog::exists(&app_path_abs) {
                 fslog::create_dir_all(app_path_abs)?;
             }
@@ -108,21 +163,53 @@ where
 
             continue;
         }
-        if installed_file.is_some() {
+        if let Some(target) = update_link {
+            //symlinks are compared by where they point, whatever the app's copy currently is.
+            let in_place = std::fs::read_link(&app_path_abs).ok().as_ref() == Some(&target);
+            new_precontent.hash = defines::HASH_SYMLINK.to_string();
+            new_precontent.link_target = Some(target);
+            if in_place {
+                info!("Found same symlink, skipping: {:?}", update_path_relative);
+            } else {
+                info!("Symlink changed or missing, updating: {:?}", update_path_relative);
+                make_parent_dir(target_directory, &app_path_abs, &mut files_in_the_way)?; //dest writability is ensured by staging next to it
+                update_list.push(UpdateFileInfo {
+                    from_path: update_path_abs.to_str().unwrap().to_owned(),
+                    to_path: app_path_abs.to_str().unwrap().to_owned(),
+                })
+            }
+        } else if installed_file.is_some() {
             //file was already installed once, compare and replace if necessary.
             let installed = installed_file.unwrap();
             let mut needs_update = true;
-            if fslog::exists(&app_path_abs) && !is_hash_whitelisted(&installed.dst_path) {
+            if is_regular_file(&app_path_abs)
+                && !is_hash_whitelisted(&installed.dst_path)
+                && installed.hash != defines::HASH_ALWAYS_REPLACE
+                && installed.hash != defines::HASH_SYMLINK
+            {
                 let sha_installed = hex::decode(&installed.hash)?;
-                let (_, sha_update) = common::get_file_hash(&update_path_abs)?;
+                let sha_update = match update_hashes.get(update_path_relative) {
+                    Some(x) => x.clone(),
+                    None => common::get_file_hash_with(&update_path_abs, installed.hash_algorithm)?,
+                };
                 needs_update = sha_installed != sha_update;
-                new_precontent.hash = hex::encode(sha_update);
+                if installed.hash_algorithm == HashAlgorithm::blake3 {
+                    new_precontent.hash = hex::encode(sha_update);
+                }
+                //older hashes stay deferred, the file gets rehashed with BLAKE3 once it is in place.
             }
             if needs_update {
                 //file has changed and needs to be updated!
                 info!("Found hash difference or missing file, updating: {:?}", update_path_relative);
                 fslog::file_open(&update_path_abs)?; //checking that it's readable.
-                std::fs::OpenOptions::new().read(true).write(true).truncate(false).create(true).open(&app_path_abs)?; //checking that it's writable
+                if is_regular_file(&app_path_abs) {
+                    //replaced by a rename, so it is the folder that must be writable. The file may well be read-only.
+                    let parent = app_path_abs.parent().unwrap().to_path_buf();
+                    if !writable_dirs.contains(&parent) {
+                        tempfile::NamedTempFile::new_in(&parent)?;
+                        writable_dirs.insert(parent);
+                    }
+                }
                 update_list.push(UpdateFileInfo {
                     from_path: update_path_abs.to_str().unwrap().to_owned(),
                     to_path: app_path_abs.to_str().unwrap().to_owned(),
@@ -134,8 +221,7 @@ where
             //this file is new, create it!
             info!("New file: {:?} .. from = {:?}", update_path_relative, update_path_abs);
             fslog::file_open(&update_path_abs)?; //checking that the source file is readable.
-            fslog::create_dir_all(&app_path_abs.parent().unwrap())?;
-            File::create(&app_path_abs)?; //making the file, and ensuring dest is writable
+            make_parent_dir(target_directory, &app_path_abs, &mut files_in_the_way)?; //dest writability is ensured by staging next to it
 
             update_list.push(UpdateFileInfo {
                 from_path: update_path_abs.to_str().unwrap().to_owned(),
@@ -149,18 +235,47 @@ where
             new_precontent,
         )?);
         progress_cb(
-            ((current_index as f64) / (total_count as f64) * 0.25 + 0.5) as f32,
+            ((current_index as f64) / (total_count as f64) * 0.05 + 0.7) as f32,
             InstallProgressSegment::Installing,
         );
         current_index += 1;
     }
-    Ok((update_list, updated_file_list))
+    Ok((update_list, updated_file_list, files_in_the_way))
+}
+/// Creates the folder path goes into, unless a file of the installed version stands where one of its directories goes.
+/// That file is added to files_in_the_way instead, it is only moved aside once the journal is written.
+fn make_parent_dir(
+    target_directory: &PathBuf,
+    path: &PathBuf,
+    files_in_the_way: &mut Vec<PathBuf>,
+) -> Result<(), FormattedError> {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == target_directory.as_path() || !dir.starts_with(target_directory) {
+            break;
+        }
+        //below a file nothing exists, so the first existing ancestor decides.
+        if fslog::get_simple_fs_meta_symlink(dir).is_some() {
+            if !is_real_dir(dir) {
+                if !files_in_the_way.iter().any(|x| x == dir) {
+                    warn!("WARNING, file -> dir detected, replacing: {:?}", dir);
+                    files_in_the_way.push(dir.to_path_buf());
+                }
+                return Ok(());
+            }
+            break;
+        }
+        current = dir.parent();
+    }
+    fslog::create_dir_all(path.parent().unwrap())?;
+    return Ok(());
 }
 
 fn diff_update<F>(
     downloaded_file: &PathBuf,
     target_directory: &PathBuf,
     params: &mut StoredInstallData,
+    repair: Option<&HashSet<PathBuf>>,
     progress_cb: F,
 ) -> Result<(), FormattedError>
 where
@@ -168,19 +283,24 @@ where
 {
     /*
         The most important function in this project. Takes a source file, target directory, and install params
-        This function determines if the unzips the source file to a temp folder
+        This function unzips the source file to the staging folder ('temp folder')
 
         Then the diffing algorithm is run over the contents of 'temp folder' and 'target directory', once for every item in 'temp folder'.
-        It starts by opening each equivalent file in 'target directory' for writing. If it is successful the file gets added to the list of copy-targets.
-        The file is then closed to respect ulimit.
+        It starts by checking that the folder of each equivalent file in 'target directory' is writable, by creating and removing
+        a temporary file in it. If it is successful the file gets added to the list of copy-targets.
 
         If the 'temp file' hash matches the hash of the file with the same name in the 'target directory' (stored in 'install params'),
         it is not added to the list of copy-targets.
 
-        Once all the files in 'target directory' have been evaluated to be writable, they are truncated and written to with the contents of their equivalent
-        file in 'temp folder'.
+        Once all the files in 'target directory' have been evaluated to be writable, the new versions are staged next to their targets.
+        A journal of every target, its staged file and its backup is stored before any target is touched.
+        The targets are then swapped with the staged files using renames. Should anything fail, the journal is replayed
+        to put the previous version back, the same happens on the next launch if the process dies mid-swap.
+
+        Then, it cleans up the old files that are no longer needed. Replaced and obsolete files of the previous version
+        are moved to the previous folder instead of being deleted, so the update can be undone with --pakkly_rollback.
 
-        Then, it cleans up the old files that are no longer needed.
+        A repair passes the files to restore, only those are swapped and the retained previous version is kept as is.
 
         Returns the list of relative paths that constituted this update, including ones whos hash matched, for easier processing.
     */
@@ -189,11 +309,18 @@ where
     info!("fresh_install={}", fresh_install);
     info!("Updating...");
 
-    let zip_tmpdir = tempdir()?;
-    let unzip_path = zip_tmpdir.path().to_path_buf();
+    let unzip_path = paths::get_install_staging_dir();
+    if fslog::exists(&unzip_path) {
+        //leftover from an update that could not be resumed.
+        fslog::remove_dir_all(&unzip_path)?;
+    }
+    fslog::create_dir_all(&unzip_path)?;
 
     let mut empty = [].to_vec();
-    unzip::extract(&downloaded_file, &unzip_path, &progress_cb)?;
+    //the staging dir shares the volume with the install, running out of space later would be far worse.
+    let limits = &params.fetched_meta.app.archive_limits;
+    common::ensure_free_space(&unzip_path, archive::validate(&downloaded_file, limits)?)?;
+    archive::extract(&downloaded_file, &unzip_path, limits, &progress_cb)?;
 
     let shipperfile = get_shipperfile(&unzip_path)?;
     params.shipperfile = Some(shipperfile);
@@ -211,83 +338,372 @@ where
     let update_files: Vec<PathBuf> = common::all_relative_files_in_folder_recursive(&unzip_path)?;
     log::info!("Update files: {:?}", update_files);
     //open all files for writing BEFORE writing to them to ensure proper permissions.
-    let (update_list, mut updated_file_list) =
+    let (mut update_list, mut updated_file_list, mut files_in_the_way) =
         check_files(&update_files, &unzip_path, target_directory, &app_files, &progress_cb)?;
     info!("All writes checked and hashes calculated...");
+    if let Some(damaged) = repair {
+        update_list.retain(|x| match PathBuf::from(&x.to_path).strip_prefix(target_directory) {
+            Ok(relative) => damaged.contains(relative),
+            Err(_) => false,
+        });
+        //files only the download has were never installed, they stay that way.
+        updated_file_list.retain(|x| {
+            damaged.contains(&x.dst_path)
+                || fslog::get_simple_fs_meta_symlink(target_directory.join(&x.dst_path)).is_some()
+        });
+        files_in_the_way.retain(|x| update_list.iter().any(|y| Path::new(&y.to_path).starts_with(x)));
+        info!("Repairing {} files...", update_list.len());
+    }
 
-    if !fresh_install {
-        //mark as dirty until updating completes
-        let mut params_old = StoredInstallData::read_json()?;
+    let mut journal = journal_for(&update_list, &files_in_the_way);
+    let mut params_old = match fresh_install {
+        false => Some(StoredInstallData::read_json()?),
+        true => None,
+    };
+    if let Some(params_old) = params_old.as_mut() {
+        //mark as dirty until updating completes, along with the journal needed to undo it after a crash.
         params_old.installing = true;
+        params_old.pending_install = Some(PendingInstall {
+            phase: InstallPhase::Staging,
+            staging_dir: unzip_path.clone(),
+            journal: journal.clone(),
+            new_files: updated_file_list.clone(),
+            app_info: params.fetched_meta.app.clone(),
+            shipperfile: params.shipperfile.clone(),
+            repair: repair.is_some(),
+            previous_dropped: false,
+        });
         params_old.write_json()?;
     }
 
-    //all handles are openable, if we got here it means we can safely update. This next section must never error! If it does the install is corrupted.
+    //place every new file next to its target, nothing of the current install is touched yet.
+    info!("Staging {} files...", journal.len());
+    let staging = stage_files(&update_list, &journal, &progress_cb);
+    if staging.is_err() {
+        rollback_journal(&journal);
+        abandon_pending_install(params_old.as_mut());
+        return staging;
+    }
+
+    //from here on every change is journaled, a failure or crash restores the previous version.
     info!("Starting critical section...");
-    {
-        let total_count = update_list.len();
-        let mut current_index = 0;
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.resize(1024 * 1024 * 20, 0x00);
-        for handle in update_list {
-            let mut h_from = fslog::file_open(&handle.from_path).unwrap();
-            let mut h_to = std::fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .truncate(false)
-                .create(true)
-                .open(&handle.to_path)?;
-            buffer.fill(0x00);
-            h_to.set_len(0).unwrap(); //this error must never fire!
-            loop {
-                let length = h_from.read(buffer.as_mut_slice()).unwrap();
-                if length == 0 {
-                    break;
-                }
-                let wlen = h_to.write(&buffer.as_slice()[0..length]).unwrap();
-                if wlen == 0 {
-                    panic!("Wrote 0 length!");
+    let mut previous = match repair {
+        Some(_) => None,
+        None => params_old.as_ref().map(RetainedVersion::of),
+    };
+    if let Some(params_old) = params_old.as_mut() {
+        params_old.pending_install.as_mut().unwrap().phase = InstallPhase::Swapping;
+        let marked = params_old.write_json();
+        if marked.is_err() {
+            rollback_journal(&journal);
+            abandon_pending_install(Some(params_old));
+            return marked;
+        }
+    }
+    let swap = swap_files(&mut journal, params_old.as_mut(), &progress_cb);
+    if swap.is_err() {
+        error!("Swapping files failed, restoring the previous version...");
+        rollback_journal(&journal);
+        abandon_pending_install(params_old.as_mut());
+        return swap;
+    }
+    if let Some(params_old) = params_old.as_mut() {
+        //committed, a crash from here on finishes the update instead.
+        params_old.pending_install.as_mut().unwrap().phase = InstallPhase::Cleanup;
+        common::warn_unwrap(params_old.write_json());
+        if previous.is_some() && !drop_retained_version(params_old) {
+            previous = None;
+            params.previous_version = None;
+        }
+    }
+
+    progress_cb(0.99, InstallProgressSegment::Installing);
+    let app_info = match repair {
+        Some(_) => None,
+        None => Some(params.fetched_meta.app.clone()),
+    };
+    finish_update(params, updated_file_list, app_info, target_directory, &journal, fresh_install, previous)?;
+    progress_cb(1.0, InstallProgressSegment::Installing);
+    info!("DIFFUPDATE complete");
+    Ok(())
+}
+fn is_real_dir<P: AsRef<Path>>(path: P) -> bool {
+    return fslog::get_simple_fs_meta_symlink(path).map_or(false, |x| x.is_directory);
+}
+fn is_regular_file(path: &PathBuf) -> bool {
+    return fslog::get_simple_fs_meta_symlink(path).map_or(false, |x| x.is_file);
+}
+/// One entry per file of update_list in the same order, followed by one for every file that becomes a directory.
+fn journal_for(update_list: &Vec<UpdateFileInfo>, files_in_the_way: &Vec<PathBuf>) -> Vec<JournalEntry> {
+    let mut journal: Vec<JournalEntry> = update_list
+        .iter()
+        .map(|x| JournalEntry {
+            target: x.to_path.clone(),
+            staged: format!("{}{}", x.to_path, defines::STAGED_FILE_SUFFIX),
+            //files that did not exist before get deleted on rollback instead.
+            backup: match fslog::get_simple_fs_meta_symlink(&x.to_path) {
+                Some(_) => Some(format!("{}{}", x.to_path, defines::BACKUP_FILE_SUFFIX)),
+                None => None,
+            },
+            directory: false,
+        })
+        .collect();
+    for file in files_in_the_way {
+        let target = file.to_str().unwrap().to_owned();
+        journal.push(JournalEntry {
+            staged: format!("{}{}", target, defines::STAGED_FILE_SUFFIX),
+            backup: Some(format!("{}{}", target, defines::BACKUP_FILE_SUFFIX)),
+            target,
+            directory: true,
+        });
+    }
+    return journal;
+}
+fn stage_files<F>(
+    update_list: &Vec<UpdateFileInfo>,
+    journal: &Vec<JournalEntry>,
+    progress_cb: F,
+) -> Result<(), FormattedError>
+where
+    F: Fn(f32, InstallProgressSegment),
+{
+    //the directories files are staged into come first, in place of the files moved aside.
+    for entry in journal.iter().filter(|x| x.directory) {
+        if fslog::get_simple_fs_meta_symlink(&entry.target).is_some() && !is_real_dir(&entry.target) {
+            fslog::rename(&entry.target, entry.backup.as_ref().unwrap())?;
+        }
+        fslog::create_dir_all(&entry.target)?;
+    }
+    let total_count = update_list.len();
+    let pairs: Vec<(&UpdateFileInfo, &JournalEntry)> = update_list.iter().zip(journal).collect();
+    common::parallel_map(
+        &pairs,
+        || {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.resize(1024 * 1024 * 4, 0x00);
+            return buffer;
+        },
+        |buffer, (handle, entry)| {
+            if let Some(parent) = Path::new(&entry.staged).parent() {
+                if !fslog::exists(parent) {
+                    fslog::create_dir_all(parent)?;
                 }
             }
-            #[cfg(unix)]
-            {
-                let metadata = h_from.metadata().unwrap();
-                fs::set_permissions(&handle.to_path, metadata.permissions()).unwrap();
+            //the staging directory lives on the install volume, so this is usually just a rename.
+            if fslog::rename(&handle.from_path, &entry.staged).is_err() {
+                let staged = UpdateFileInfo { from_path: handle.from_path.clone(), to_path: entry.staged.clone() };
+                write_update_file(&staged, buffer)?;
             }
-            drop(h_to);
-            drop(h_from);
+            Ok(())
+        },
+        |done| {
             progress_cb(
-                ((current_index as f64) / (total_count as f64) * 0.23 + 0.75) as f32,
+                ((done as f64) / (total_count as f64) * 0.15 + 0.75) as f32,
                 InstallProgressSegment::Installing,
             );
-            current_index += 1;
+        },
+    )?;
+    Ok(())
+}
+/// Swaps every target with its staged file. params_json holds the stored journal, which is kept up to date
+/// with every entry that changes on the way so rollback_journal after a crash sees what was actually done.
+fn swap_files<F>(
+    journal: &mut Vec<JournalEntry>,
+    mut params_json: Option<&mut StoredInstallData>,
+    progress_cb: F,
+) -> Result<(), FormattedError>
+where
+    F: Fn(f32, InstallProgressSegment),
+{
+    let total_count = journal.len();
+    for current_index in 0..total_count {
+        let entry = &journal[current_index];
+        if entry.directory {
+            //moved aside when staging.
+            continue;
+        }
+        if let Some(backup) = &entry.backup {
+            if fslog::get_simple_fs_meta_symlink(&entry.target).is_some() {
+                fslog::rename(&entry.target, backup)?;
+            } else {
+                //removed since the journal was written, a rollback must delete what takes its place.
+                journal[current_index].backup = None;
+                if let Some(params_json) = params_json.as_mut() {
+                    params_json.pending_install.as_mut().unwrap().journal = journal.clone();
+                    params_json.write_json()?;
+                }
+            }
         }
+        let entry = &journal[current_index];
+        fslog::rename(&entry.staged, &entry.target)?;
+        progress_cb(
+            ((current_index as f64) / (total_count as f64) * 0.08 + 0.9) as f32,
+            InstallProgressSegment::Installing,
+        );
     }
+    Ok(())
+}
+/// Replays the journal backwards, putting every target back the way it was before the update.
+/// Safe to run on a journal in any state, entries that were never swapped are left alone.
+fn rollback_journal(journal: &Vec<JournalEntry>) {
+    for entry in journal.iter().rev() {
+        match &entry.backup {
+            Some(backup) => {
+                if fslog::get_simple_fs_meta_symlink(backup).is_some() {
+                    //a directory can neither replace nor be replaced by a rename.
+                    if is_real_dir(backup) || is_real_dir(&entry.target) {
+                        common::warn_unwrap(remove_any(&entry.target));
+                    }
+                    common::warn_unwrap(fslog::rename(backup, &entry.target));
+                }
+            }
+            None => {
+                if fslog::get_simple_fs_meta_symlink(&entry.target).is_some()
+                    && fslog::get_simple_fs_meta_symlink(&entry.staged).is_none()
+                {
+                    common::warn_unwrap(fslog::remove_file(&entry.target));
+                }
+            }
+        }
+        if fslog::get_simple_fs_meta_symlink(&entry.staged).is_some() {
+            common::warn_unwrap(fslog::remove_file(&entry.staged));
+        }
+    }
+}
+/// Moves the backups of a committed journal into the previous folder, or removes them when nothing is retained.
+fn retain_backups(journal: &Vec<JournalEntry>, target_directory: &PathBuf, retain_dir: Option<&PathBuf>) {
+    for entry in journal {
+        if let Some(backup) = &entry.backup {
+            if fslog::get_simple_fs_meta_symlink(backup).is_none() {
+                continue;
+            }
+            if let (Some(retain_dir), Ok(relative)) =
+                (retain_dir, PathBuf::from(&entry.target).strip_prefix(target_directory))
+            {
+                if retain_file(backup, &retain_dir.join(relative)).is_ok() {
+                    continue;
+                }
+                warn!("Could not retain {}, the previous version will be incomplete.", entry.target);
+            }
+            common::warn_unwrap(remove_any(backup));
+        }
+    }
+}
+fn retain_file<P: AsRef<std::path::Path>>(from: P, to: &PathBuf) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fslog::create_dir_all(parent)?;
+    }
+    return fslog::rename(from, to);
+}
+/// Removes a file, symlink or whole directory, nothing if path does not exist.
+fn remove_any<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    return match fslog::get_simple_fs_meta_symlink(&path) {
+        None => Ok(()),
+        Some(_) if is_real_dir(&path) => fslog::remove_dir_all(&path),
+        Some(_) => fslog::remove_file(&path),
+    };
+}
+fn remove_previous_version() -> std::io::Result<()> {
+    let previous_dir = paths::get_install_previous_dir();
+    if fslog::exists(&previous_dir) {
+        fslog::remove_dir_all(&previous_dir)?;
+    }
+    return Ok(());
+}
+/// Only one version is retained, the one a committed update replaced takes the place of the one before.
+/// Recorded in the pending install, a resumed cleanup must not throw away what it already retained of this update.
+/// False if the previous folder could not be cleared, nothing is retained then.
+fn drop_retained_version(params_json: &mut StoredInstallData) -> bool {
+    if params_json.pending_install.as_ref().map_or(true, |x| x.previous_dropped) {
+        return true;
+    }
+    params_json.previous_version = None;
+    let removed = remove_previous_version();
+    if let Err(e) = &removed {
+        warn!("Could not remove the retained version, the replaced one will not be kept: {:?}", e);
+    }
+    params_json.pending_install.as_mut().unwrap().previous_dropped = removed.is_ok();
+    common::warn_unwrap(params_json.write_json());
+    return removed.is_ok();
+}
+fn abandon_pending_install(params_old: Option<&mut StoredInstallData>) {
+    if let Some(params_old) = params_old {
+        params_old.installing = false;
+        params_old.pending_install = None;
+        common::warn_unwrap(params_old.write_json());
+    }
+    common::warn_unwrap(fslog::remove_dir_all(paths::get_install_staging_dir()));
+}
+fn write_update_file(handle: &UpdateFileInfo, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+    return common::copy_file_preserving(&handle.from_path, &handle.to_path, buffer);
+}
+/// Everything after the critical section: fulfills deferred hashes, retains the replaced version,
+/// removes obsolete files and the staging directory. app_info is the version installed, None for a repair.
+/// The new state is stored in one write, whatever is left to do after it is only cleaning up.
+fn finish_update(
+    params: &mut StoredInstallData,
+    mut updated_file_list: Vec<InstalledFile>,
+    app_info: Option<DownloadParams>,
+    target_directory: &PathBuf,
+    journal: &Vec<JournalEntry>,
+    fresh_install: bool,
+    previous: Option<RetainedVersion>,
+) -> Result<(), FormattedError> {
+    let previous_dir = paths::get_install_previous_dir();
+    let retain_dir = match previous.is_some() {
+        true => Some(&previous_dir),
+        false => None,
+    };
+    retain_backups(journal, target_directory, retain_dir);
 
     let bad_hash = defines::HASH_DEFER.to_string();
-    for file in &mut updated_file_list {
-        if file.hash == bad_hash {
+    let deferred: Vec<usize> =
+        (0..updated_file_list.len()).filter(|x| updated_file_list[*x].hash == bad_hash).collect();
+    let rehashed = common::parallel_map(
+        &deferred,
+        || (),
+        |_, x| {
+            let mut file = updated_file_list[*x].clone();
             file.rehash()?;
-        }
+            Ok(file)
+        },
+        |_| {},
+    )?;
+    for (index, file) in deferred.into_iter().zip(rehashed) {
+        updated_file_list[index] = file;
     }
 
+    let installed_old = std::mem::replace(&mut params.installed_files, updated_file_list);
+    if previous.is_some() {
+        params.previous_version = previous;
+    }
+    if let Some(app_info) = app_info {
+        params.installed_app_info = app_info; //set the installed version!
+        params.skipped_app_version = None;
+        //watch the new version, unless there is nothing to go back to.
+        params.health_pending = params.previous_version.is_some();
+        params.failed_launches = 0;
+    }
     if !fresh_install {
         //mark as clean, we've passed the critical section
         params.installing = false;
+        params.pending_install = None;
         params.write_json()?;
     }
 
-    progress_cb(0.99, InstallProgressSegment::Installing);
     //clean and remove old files
-    let cleanup = cleanup_obsolete_files(&params.installed_files, &updated_file_list, target_directory);
+    let cleanup = cleanup_obsolete_files(&installed_old, &params.installed_files, target_directory, retain_dir);
     if cleanup.is_err() {
         warn!("Warning: Cleanup of obsolete files failed!");
     }
+    common::warn_unwrap(fslog::remove_dir_all(paths::get_install_staging_dir()));
     #[cfg(unix)]
     {
         let exe_path = common::find_executable_path(&params, None).unwrap();
         if let Some(path_unwrapped) = exe_path {
-            fs::set_permissions(&path_unwrapped, fs::Permissions::from_mode(0o744))?;
+            //on top of the mode from the archive, never below what the app needs to start.
+            let mode = fs::metadata(&path_unwrapped)?.permissions().mode();
+            fs::set_permissions(&path_unwrapped, fs::Permissions::from_mode(mode | 0o744))?;
         } else {
             let e = "Exe path not found for permission set.";
             error!("{}", e);
@@ -295,15 +711,139 @@ where
             return Err(FormattedError::from_str(e.to_string()));
         }
     }
-    progress_cb(1.0, InstallProgressSegment::Installing);
-    info!("DIFFUPDATE complete");
-    params.installed_files = updated_file_list;
     Ok(())
 }
+/// Deals with an update that was interrupted by a crash or power loss.
+/// Committed updates are finished, anything before that is rolled back by replaying the journal.
+/// A rolled back install keeps the previous version's file list and is forced to update again.
+pub fn resume_interrupted_install() -> Result<(), FormattedError> {
+    let mut params = match StoredInstallData::read_json_unchecked() {
+        Ok(x) => x,
+        Err(_) => return Ok(()), //not installed
+    };
+    if !params.installing {
+        return Ok(());
+    }
+    warn!("Interrupted install detected!");
+    match params.pending_install.clone() {
+        Some(pending) if pending.phase == InstallPhase::Cleanup => {
+            info!("Finishing interrupted install of version {}", pending.app_info.version);
+            let mut previous = match pending.repair {
+                true => None,
+                false => Some(RetainedVersion::of(&params)),
+            };
+            if previous.is_some() && !drop_retained_version(&mut params) {
+                previous = None;
+            }
+            params.shipperfile = pending.shipperfile;
+            let target_directory = paths::get_install_path();
+            let app_info = match pending.repair {
+                true => None,
+                false => Some(pending.app_info),
+            };
+            finish_update(
+                &mut params,
+                pending.new_files,
+                app_info,
+                &target_directory,
+                &pending.journal,
+                false,
+                previous,
+            )?;
+            installer_tools::replace_meta_files(&mut params)?;
+            info!("Interrupted install finished.");
+            return Ok(());
+        }
+        Some(pending) => {
+            warn!("Rolling back interrupted install of version {}", pending.app_info.version);
+            rollback_journal(&pending.journal);
+        }
+        None => {
+            warn!("Interrupted install has no journal, will update again.");
+        }
+    }
+    common::warn_unwrap(fslog::remove_dir_all(paths::get_install_staging_dir()));
+    params.installing = false;
+    params.pending_install = None;
+    params.last_launch = 0; //do not let the update timer skip the check
+    params.write_json()?;
+    return Ok(());
+}
+/// Restores the version retained by the last update.
+/// The version rolled back from is skipped until the server offers a different one.
+pub fn rollback(params: &mut StoredInstallData) -> Result<(), FormattedError> {
+    let previous = match params.previous_version.clone() {
+        Some(x) => x,
+        None => return Err(FormattedError::from_str("No previous version is available to roll back to.".to_string())),
+    };
+    let target_directory = paths::get_install_path();
+    let previous_dir = paths::get_install_previous_dir();
+    info!("Rolling back from {} to {}", params.installed_app_info.version, previous.app_info.version);
+
+    let mut update_list: Vec<UpdateFileInfo> = vec![];
+    for file in &previous.installed_files {
+        let retained = previous_dir.join(&file.dst_path);
+        let target = target_directory.join(&file.dst_path);
+        if fslog::get_simple_fs_meta_symlink(&retained).is_some() {
+            update_list.push(UpdateFileInfo {
+                from_path: retained.to_str().unwrap().to_string(),
+                to_path: target.to_str().unwrap().to_string(),
+            });
+        } else if fslog::get_simple_fs_meta_symlink(&target).is_none() {
+            //neither replaced nor removed by the update, yet gone.
+            return Err(FormattedError::from_str(format!(
+                "Previous version is incomplete, missing {}",
+                file.dst_path_human
+            )));
+        }
+    }
+    for handle in &update_list {
+        if let Some(parent) = PathBuf::from(&handle.to_path).parent() {
+            fslog::create_dir_all(parent)?;
+        }
+    }
+
+    //copied rather than moved, the retained files must survive a failed rollback.
+    let mut journal = journal_for(&update_list, &vec![]);
+    let mut buffer: Vec<u8> = Vec::new();
+    buffer.resize(1024 * 1024 * 20, 0x00);
+    for (handle, entry) in update_list.iter().zip(&journal) {
+        let staged = UpdateFileInfo { from_path: handle.from_path.clone(), to_path: entry.staged.clone() };
+        if let Err(e) = write_update_file(&staged, &mut buffer) {
+            rollback_journal(&journal);
+            return Err(e.into());
+        }
+    }
+    //not resumable, --pakkly_rollback keeps no journal.
+    let swap = swap_files(&mut journal, None, |_a, _b| {});
+    if swap.is_err() {
+        error!("Rollback failed, keeping the current version...");
+        rollback_journal(&journal);
+        return swap;
+    }
+    retain_backups(&journal, &target_directory, None);
+    let cleanup = cleanup_obsolete_files(&params.installed_files, &previous.installed_files, &target_directory, None);
+    if cleanup.is_err() {
+        warn!("Warning: Cleanup of rolled back files failed!");
+    }
+
+    params.skipped_app_version = Some(params.installed_app_info.version.clone());
+    params.installed_app_info = previous.app_info;
+    params.installed_files = previous.installed_files;
+    params.shipperfile = previous.shipperfile;
+    params.previous_version = None;
+    params.health_pending = false;
+    params.failed_launches = 0;
+    installer_tools::replace_meta_files(params)?;
+    common::warn_unwrap(remove_previous_version());
+    info!("Rollback complete.");
+    return Ok(());
+}
 fn cleanup_obsolete_files(
     installed_old: &Vec<InstalledFile>,
     installed_new: &Vec<InstalledFile>,
     target_directory: &PathBuf,
+    retain_dir: Option<&PathBuf>,
 ) -> Result<(), FormattedError> {
     let mut cleanup_dirs: Vec<PathBuf> = vec![];
     let mut cleanup_files: Vec<PathBuf> = vec![];
@@ -313,16 +853,31 @@ fn cleanup_obsolete_files(
         if file_kept.is_none() {
             let mut p: PathBuf = PathBuf::from(target_directory);
             p.push(&file.dst_path);
-            if p.is_dir() {
+            if is_real_dir(&p) {
+                //a file the update turned into a directory, its backup has been retained already.
+                if installed_new.iter().any(|x| x.dst_path.starts_with(&file.dst_path)) {
+                    continue;
+                }
                 cleanup_dirs.push(p);
             } else {
-                cleanup_files.push(p);
+                cleanup_files.push(file.dst_path.clone());
             }
         }
     }
     for file in cleanup_files {
-        trace!("Removing obsolete file: {:?}", file);
-        fslog::remove_file(file)?;
+        let p = target_directory.join(&file);
+        if fslog::get_simple_fs_meta_symlink(&p).is_none() {
+            //went along with a directory the update replaced by a file.
+            continue;
+        }
+        if let Some(retain_dir) = retain_dir {
+            trace!("Retaining obsolete file: {:?}", p);
+            if retain_file(&p, &retain_dir.join(&file)).is_ok() {
+                continue;
+            }
+        }
+        trace!("Removing obsolete file: {:?}", p);
+        fslog::remove_file(p)?;
     }
     cleanup_dirs.sort_by(|a, b| {
         //sort by length so that a/b/c gets deleted before a
@@ -10,7 +10,7 @@ use pakkly_error::{ferror, FormattedError};
 use walkdir::WalkDir;
 
 #[cfg(target_os = "linux")]
-use crate::webview_alert;
+use crate::frontend;
 
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::PermissionsExt;
@@ -84,6 +84,23 @@ pub fn windows_registry_teardown() -> Result<(), FormattedError> {
     return Ok(());
 }
 
+#[cfg(target_os = "linux")]
+pub fn linux_xdg_teardown(parameters: &StoredInstallData) -> Result<(), FormattedError> {
+    use crate::os_spec::linux;
+    if common::arg_flag_set(defines::PAKKLY_CLI_NOROOT) {
+        return Ok(());
+    }
+    //the desktop entry and mime package symlinks are already gone at this point, the databases still list them.
+    let has_associations = match &parameters.shipperfile {
+        Some(shipperfile) => linux::desktop_mime_types(shipperfile).len() > 0,
+        None => false,
+    };
+    if has_associations {
+        linux::refresh_xdg_databases();
+    }
+    return Ok(());
+}
+
 fn place_icons_and_shortcuts(parameters: &mut StoredInstallData, fresh: bool) -> Result<(), FormattedError> {
     if parameters.shipperfile.is_none() {
         return Err(ferror!("place_icons_and_shortcuts called without shipperfile"));
@@ -144,24 +161,38 @@ fn place_icons_and_shortcuts(parameters: &mut StoredInstallData, fresh: bool) ->
                 );
             }
 
+            let mime_types = linux::desktop_mime_types(shipperfile);
+            let (exec_suffix, mime_line) = match mime_types.len() {
+                0 => ("", "".to_string()),
+                _ => (" %U", format!("\nMimeType={};", mime_types.join(";"))),
+            };
             let desktop_data = format!(
                 "[Desktop Entry]
 Encoding=UTF-8
 Type=Application
 Name={}
 Comment={}
-Exec={}
+Exec={}{}
 Icon={}
 Terminal=false
 Categories=GNOME;Application;
-StartupNotify=true",
+StartupNotify=true{}",
                 parameters.fetched_meta.app_name,
                 parameters.fetched_meta.description.clone().unwrap_or("".to_string()),
                 common::path_str(&pakkly_installed),
-                png_symlink
+                exec_suffix,
+                png_symlink,
+                mime_line
             );
             let desktop_file = shipper_dir.join("linux.desktop");
-            let desktop_symlink = format!("/usr/share/applications/pak_{}.desktop", *PAKKLY_ID_CLEAN);
+            let desktop_symlink = format!("{}/pak_{}.desktop", linux::XDG_APPLICATIONS_DIR, *PAKKLY_ID_CLEAN);
+
+            //the desktop database only needs a refresh if the declared types changed.
+            let old_mime_line = fslog::read_to_string(&desktop_file)
+                .ok()
+                .and_then(|x| x.lines().find(|l| l.starts_with("MimeType=")).map(|l| format!("\n{}", l)))
+                .unwrap_or("".to_string());
+            let mut associations_changed = old_mime_line != mime_line;
 
             fslog::write(&desktop_file, desktop_data)?;
             if fresh {
@@ -172,13 +203,38 @@ StartupNotify=true",
                 );
             }
 
+            let mime_file = shipper_dir.join("mime.xml");
+            let mime_symlink = format!("{}/packages/pak_{}.xml", linux::XDG_MIME_DIR, *PAKKLY_ID_CLEAN);
+            let mime_definitions = shipperfile.mime_definitions.clone().unwrap_or(vec![]);
+            if mime_definitions.len() > 0 {
+                let mime_data = linux::mime_package_xml(&mime_definitions);
+                let old_mime_data = fslog::read_to_string(&mime_file).unwrap_or("".to_string());
+                fslog::write(&mime_file, &mime_data)?;
+                if fresh || old_mime_data != mime_data {
+                    associations_changed = true;
+                    linsudo.command("rm".to_string(), ["-f".to_string(), mime_symlink.clone()].to_vec());
+                    linsudo.command(
+                        "ln".to_string(),
+                        ["-s".to_string(), mime_file.to_str().unwrap().to_string(), mime_symlink.clone()].to_vec(),
+                    );
+                }
+            } else if fslog::exists(&mime_file) {
+                //the definitions were dropped in this update.
+                associations_changed = true;
+                fslog::remove_file(&mime_file)?;
+                linsudo.command("rm".to_string(), ["-f".to_string(), mime_symlink.clone()].to_vec());
+            }
+            if fresh || associations_changed {
+                linux::queue_xdg_refresh(&mut linsudo);
+            }
+
             //linsudo.command("ln".to_string(), ["-s".to_string(),pakkly_installed.to_string(),"/usr/bin".to_string()].to_vec());
             if fresh {
                 let e = linsudo.flush();
                 if e.is_err() {
                     let err = e.unwrap_err();
                     if err.is_missing_sudo {
-                        webview_alert::alert(
+                        frontend::alert(
                             "Not root.",
                             "This program needs to be run as root to complete the initial installation.",
                             None,
@@ -186,6 +242,9 @@ StartupNotify=true",
                         common::exit(1);
                     }
                 }
+            } else if associations_changed && !linsudo.is_empty() {
+                info!("File associations changed, re-registering...");
+                common::warn_unwrap(linsudo.flush());
             }
             let uninstall_bash_file = Path::new(&paths::get_install_dir_pakkly()).join("uninstall.sh");
             fslog::write(
@@ -203,6 +262,12 @@ StartupNotify=true",
             parameters.installed_files_meta.push(InstalledFile::new(&desktop_symlink)?);
             parameters.installed_files_meta.push(InstalledFile::new(&png_file)?);
             parameters.installed_files_meta.push(InstalledFile::new(&desktop_file)?);
+            if mime_definitions.len() > 0 {
+                if fslog::get_simple_fs_meta_symlink(&mime_symlink).is_some() {
+                    parameters.installed_files_meta.push(InstalledFile::new(&mime_symlink)?);
+                }
+                parameters.installed_files_meta.push(InstalledFile::new(&mime_file)?);
+            }
             //parameters.installed_files_meta.push(InstalledFile::new(&lnkfile)?);
         }
     }
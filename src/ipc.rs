@@ -1,11 +1,30 @@
-use std::{fs, path::PathBuf, process};
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use pakkly_error::FormattedError;
+use fs2::FileExt;
+use log::info;
+use pakkly_error::{ferror, FormattedError};
 use serde::{Deserialize, Serialize};
 #[cfg(target_os = "windows")]
 use windows_interface;
 
-use crate::paths;
+use crate::{defines, paths};
+
+// Instances are tracked with two files per PID: `<pid>.json` holding the ExternalIPCInfo, and `<pid>.lock`,
+// which the owner keeps exclusively locked for as long as it runs. The OS drops the lock when the owner dies,
+// so a lockable entry is always stale, regardless of PID reuse.
+static LOCK_EXTENSION: &str = ".lock";
+static LOCK_TMP_EXTENSION: &str = ".locktmp";
+/// Held while a shipper decides whether to launch the app, until the launched app is published.
+static LAUNCH_LOCK_NAME: &str = "launching";
+/// Attempts at a reservation that enumerate did not snatch away as stale, see IPCInfo::reserve
+static RESERVE_ATTEMPTS: usize = 10;
+static RESERVATION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 pub fn erase_all() -> Result<(), FormattedError> {
     let p = paths::get_ipc_dir();
     fs::remove_dir_all(p)?;
@@ -26,36 +45,85 @@ pub fn focus(target: &ExternalIPCInfo) -> Result<(), FormattedError> {
     }
     #[cfg(target_os = "linux")]
     {
-        return Err(pakkly_error::ferror!("No focus for OS {}", crate::defines::OS_NAME));
+        use crate::os_spec::linux;
+        return linux::focus_app_by_pid(i64::from_str_radix(&target.pid, 10)?);
     }
 }
-fn running_map(pids: &Vec<i64>) -> Vec<bool> {
-    #[cfg(not(target_os = "macos"))]
+/// Sends the arguments and working directory of this launch to a running instance.
+/// The app receives them on the socket passed to it in PAKKLY_CLI_LAUNCH_SOCKET_ENV, as a single line of JSON per connection.
+pub fn forward_launch(target: &ExternalIPCInfo, args: &Vec<String>) -> Result<(), FormattedError> {
+    let socket = match &target.launch_socket {
+        Some(x) => x,
+        None => return Err(ferror!("Instance {} has no launch socket", target.pid)),
+    };
+    let launch = ForwardedLaunch {
+        args: args.to_owned(),
+        working_directory: std::env::current_dir()?.to_string_lossy().to_string(),
+    };
+    #[cfg(unix)]
     {
-        use sysinfo::{PidExt, ProcessRefreshKind, RefreshKind, System, SystemExt};
-        let mut ret = Vec::new();
-        ret.resize(pids.len(), false);
-        let prk = ProcessRefreshKind::new();
-        let rk = RefreshKind::new().with_processes(prk);
-        let mut sys = System::new_with_specifics(rk);
-        sys.refresh_processes_specifics(prk);
-        for (pid, _process) in sys.processes() {
-            let pid_64: i64 = pid.as_u32().into();
-            let found_pid = pids.iter().position(|x| *x == pid_64);
-            if found_pid.is_some() {
-                ret[found_pid.unwrap()] = true;
-            }
-        }
-        return ret;
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+        let mut stream = UnixStream::connect(socket)?;
+        stream.set_write_timeout(Some(std::time::Duration::from_secs(3)))?;
+        stream.write_all(format!("{}\n", serde_json::to_string(&launch)?).as_bytes())?;
+        stream.flush()?;
+        return Ok(());
     }
-
-    #[cfg(target_os = "macos")]
+    #[cfg(not(unix))]
     {
-        use crate::os_spec::mac;
-        let running_pids = mac::get_running_pids();
-        return pids.iter().map(|x| running_pids.contains(x)).collect();
+        let _ = (socket, launch);
+        return Err(ferror!("No launch forwarding for OS {}", crate::defines::OS_NAME));
+    }
+}
+fn is_contended(err: &std::io::Error) -> bool {
+    return err.raw_os_error() == fs2::lock_contended_error().raw_os_error();
+}
+/// Checks that the path still points at the file we opened, it might have been replaced by a new owner in the meantime.
+fn still_same_file(file: &File, path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        return match (file.metadata(), fs::metadata(path)) {
+            (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+            _ => false,
+        };
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (file, path);
+        return true;
     }
 }
+/// Removes an entry whose owner has died. Returns false if the entry is alive.
+fn remove_if_stale(lock_path: &Path, json_path: Option<&Path>) -> Result<bool, FormattedError> {
+    let lock_file = match File::open(lock_path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true), //cleaned up by someone else
+        Err(e) => return Err(e.into()),
+    };
+    if let Err(e) = FileExt::try_lock_exclusive(&lock_file) {
+        if is_contended(&e) {
+            return Ok(false);
+        }
+        return Err(e.into());
+    }
+    if !still_same_file(&lock_file, lock_path) {
+        //a new instance with the same PID took the entry over.
+        return Ok(false);
+    }
+    if let Some(json_path) = json_path {
+        let stale_info = fs::read(json_path).ok().and_then(|x| serde_json::from_slice::<ExternalIPCInfo>(&x).ok());
+        if let Some(socket) = stale_info.and_then(|x| x.launch_socket) {
+            let _ = fs::remove_file(socket);
+        }
+        let _ = fs::remove_file(json_path);
+    }
+    info!("Removing stale IPC entry: {:?}", lock_path);
+    let _ = fs::remove_file(lock_path);
+    drop(lock_file);
+    return Ok(true);
+}
 ///Returns a list of all running shippers with this Pakkly ID, excluding this one.
 pub fn enumerate() -> Result<Vec<ExternalIPCInfo>, FormattedError> {
     let ipc_dir = paths::get_ipc_dir();
@@ -63,30 +131,51 @@ pub fn enumerate() -> Result<Vec<ExternalIPCInfo>, FormattedError> {
         return Ok(vec![]);
     }
     let my_pid = process::id().to_string();
-    let dir_entries = fs::read_dir(ipc_dir)?;
-    let mut found_ipcs: Vec<i64> = vec![];
+    let dir_entries = fs::read_dir(&ipc_dir)?;
+    let mut found_ipcs: Vec<ExternalIPCInfo> = vec![];
     for dirent in dir_entries {
         if dirent.is_err() {
             return Err(dirent.unwrap_err().into());
         }
         let dirent_uw = dirent.unwrap();
-        let is_json = dirent_uw.file_name().to_string_lossy().ends_with(".json");
-        if is_json {
-            let file_content = fs::read(dirent_uw.path())?;
-            let ipc_info: ExternalIPCInfo = serde_json::from_str(&String::from_utf8(file_content)?)?;
-            if ipc_info.pid == my_pid {
-                continue;
-            }
-            found_ipcs.push(i64::from_str_radix(&ipc_info.pid, 10)?);
+        let file_name = dirent_uw.file_name().to_string_lossy().to_string();
+        if file_name.ends_with(LOCK_TMP_EXTENSION) {
+            //an entry that was never published, either being set up right now or abandoned.
+            remove_if_stale(&dirent_uw.path(), None)?;
+            continue;
+        }
+        let pid = match file_name.strip_suffix(LOCK_EXTENSION) {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        if pid == my_pid {
+            continue;
+        }
+        let json_path = pid_to_file(&pid);
+        if remove_if_stale(&dirent_uw.path(), Some(&json_path))? {
+            continue;
         }
+        let ipc_info = match fs::read(&json_path) {
+            Ok(file_content) => serde_json::from_slice(&file_content)?,
+            //the lock is what counts, the PID is in its name.
+            Err(_) => ExternalIPCInfo { pid, launch_socket: None },
+        };
+        found_ipcs.push(ipc_info);
     }
-    let only_running_mask = running_map(&found_ipcs);
-    return Ok(found_ipcs
-        .iter()
-        .enumerate()
-        .filter(|(i, _x)| only_running_mask[*i])
-        .map(|(_i, x)| ExternalIPCInfo { pid: x.to_string() })
-        .collect());
+    return Ok(found_ipcs);
+}
+/// Serializes launches, dropping it releases the lock.
+pub struct LaunchLock {
+    _file: File,
+}
+/// Waits for other shippers to finish launching. Held around enumerate, spawning the app and publishing it,
+/// so two shippers started together cannot both find no instance and both launch a single_instance app.
+pub fn lock_launch() -> Result<LaunchLock, FormattedError> {
+    init()?;
+    let path = paths::get_ipc_dir().join(LAUNCH_LOCK_NAME);
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    FileExt::lock_exclusive(&file)?;
+    return Ok(LaunchLock { _file: file });
 }
 fn init() -> Result<(), FormattedError> {
     let ipc_dir = paths::get_ipc_dir();
@@ -101,29 +190,111 @@ fn init() -> Result<(), FormattedError> {
 fn pid_to_file(pid: &str) -> PathBuf {
     return paths::get_ipc_dir().join(format!("{pid}.json"));
 }
+fn pid_to_lock(pid: &str) -> PathBuf {
+    return paths::get_ipc_dir().join(format!("{pid}{LOCK_EXTENSION}"));
+}
+/// Path of the socket a shipper hands to the app it launches, named after the shipper's PID.
+pub fn launch_socket_path() -> PathBuf {
+    return paths::get_ipc_dir().join(format!("{}.sock", process::id()));
+}
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ExternalIPCInfo {
     pid: String,
+    #[serde(default)]
+    launch_socket: Option<PathBuf>,
+}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ForwardedLaunch {
+    pub args: Vec<String>,
+    pub working_directory: String,
 }
 pub struct IPCInfo {
     ipc_data: ExternalIPCInfo,
+    lock_path: PathBuf,
+    //holds the lock until the process exits.
+    lock_file: File,
 }
 
 impl IPCInfo {
     pub fn new(pid: Option<String>) -> Result<Self, FormattedError> {
-        init()?;
         let pid_str = match pid {
             Some(x) => x,
             None => std::process::id().to_string(),
         };
-        let pidfile = pid_to_file(&pid_str);
-        let ipc_pid = Self { ipc_data: ExternalIPCInfo { pid: pid_str } };
-        fs::write(pidfile, serde_json::to_string(&ipc_pid.ipc_data)?)?;
+        let mut ipc_pid = Self::reserve(None)?;
+        ipc_pid.publish(pid_str)?;
         return Ok(ipc_pid);
     }
+    /// Takes an instance lock before the app is spawned, see hand_to_child. Must be published with the app's PID
+    /// once it is known.
+    pub fn new_for_child(launch_socket: Option<PathBuf>) -> Result<Self, FormattedError> {
+        return Self::reserve(launch_socket);
+    }
+    fn reserve(launch_socket: Option<PathBuf>) -> Result<Self, FormattedError> {
+        init()?;
+        for _ in 0..RESERVE_ATTEMPTS {
+            let reservation = RESERVATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+            let lock_path =
+                paths::get_ipc_dir().join(format!("{}_{}{}", process::id(), reservation, LOCK_TMP_EXTENSION));
+            let lock_file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&lock_path)?;
+            //until it is locked, enumerate takes it for an abandoned reservation and may unlink it.
+            match FileExt::try_lock_exclusive(&lock_file) {
+                Ok(()) if still_same_file(&lock_file, &lock_path) => {
+                    return Ok(Self {
+                        ipc_data: ExternalIPCInfo { pid: "".to_string(), launch_socket },
+                        lock_path,
+                        lock_file,
+                    });
+                }
+                Ok(()) => info!("Reservation {:?} was removed before it was locked, retrying.", lock_path),
+                Err(e) if is_contended(&e) => info!("Reservation {:?} is being removed, retrying.", lock_path),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        return Err(ferror!("Could not reserve an IPC entry in {} attempts", RESERVE_ATTEMPTS));
+    }
+    /// Lets the app spawned by command inherit the instance lock, so its entry stays alive should this shipper die
+    /// first. Only the app itself gets the descriptor, it stays close-on-exec here. Its number is passed in
+    /// PAKKLY_INSTANCE_LOCK_FD_ENV: the app must not leak it to its own children, or they keep it "running"
+    /// once it has exited.
+    pub fn hand_to_child(&self, command: &mut Command) {
+        #[cfg(unix)]
+        {
+            use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+            use std::os::unix::{io::AsRawFd, process::CommandExt};
+            let fd = self.lock_file.as_raw_fd();
+            command.env(defines::PAKKLY_INSTANCE_LOCK_FD_ENV, fd.to_string());
+            //runs in the forked child right before exec, fcntl is async-signal-safe.
+            unsafe {
+                command.pre_exec(move || {
+                    fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+                    return Ok(());
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = command;
+        }
+    }
+    pub fn publish(&mut self, pid: String) -> Result<(), FormattedError> {
+        self.ipc_data.pid = pid;
+        fs::write(pid_to_file(&self.ipc_data.pid), serde_json::to_string(&self.ipc_data)?)?;
+        //the rename keeps the locked file, enumerate only ever sees entries that are complete.
+        let published_path = pid_to_lock(&self.ipc_data.pid);
+        fs::rename(&self.lock_path, &published_path)?;
+        self.lock_path = published_path;
+        return Ok(());
+    }
     pub fn clear(&self) -> Result<(), FormattedError> {
-        let target_file = pid_to_file(&self.ipc_data.pid);
-        fs::remove_file(target_file)?;
+        if let Some(socket) = &self.ipc_data.launch_socket {
+            //the app binds it, but we own its lifetime.
+            let _ = fs::remove_file(socket);
+        }
+        if self.ipc_data.pid != "" {
+            let _ = fs::remove_file(pid_to_file(&self.ipc_data.pid));
+        }
+        fs::remove_file(&self.lock_path)?;
         Ok(())
     }
 }
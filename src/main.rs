@@ -1,6 +1,10 @@
 #![windows_subsystem = "windows"]
+mod archive;
 pub mod common;
+mod config;
 pub mod defines;
+mod embedded;
+mod frontend;
 mod fslog;
 mod installer;
 mod installer_tools;
@@ -11,21 +15,21 @@ mod paths;
 pub mod remoteinstallerdata;
 mod shipper;
 mod shipperfile;
+mod terminal;
 mod uninstaller;
 mod unzip;
+mod updatesource;
+mod verifier;
 mod webview;
 mod webview_alert;
-use crate::{remoteinstallerdata::StoredInstallData, webview_alert::ConfirmParams};
+use crate::{frontend::ConfirmParams, remoteinstallerdata::StoredInstallData};
 use chrono::Utc;
 use common::CrashState;
-use log::{error, info};
+use log::{error, info, warn};
 use pakkly_error::FormattedError;
-use std::thread;
-use std::time::Duration;
-use std::{
-    panic::PanicInfo,
-    sync::{Arc, Mutex},
-};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 fn unwrap_fe<T>(res: Result<T, FormattedError>) -> T
 where
     T: std::fmt::Debug,
@@ -37,23 +41,18 @@ where
     }
     return res.unwrap();
 }
-fn emit_panic_error(pi: &PanicInfo<'_>) {
-    let mut error_msg = "".to_string();
-    if let Some(msg) = pi.payload().downcast_ref::<&str>() {
-        error_msg = format!("Panic Message: {:?}", msg);
-    }
-    if let Some(location) = pi.location() {
-        error_msg =
-            format!("{}\nPanic location:  {:?} at line {:?}", error_msg, location.file(), location.line()).to_string();
-    } else {
-        error_msg += "\nPanic in unknown file";
-    }
-    error!("{}", error_msg);
-}
 fn install_quiet_exit_hook(local_data: &mut StoredInstallData) {
     let install_quiet = common::arg_flag_set(defines::PAKKLY_CLI_INSTALL_QUIET);
     if install_quiet {
-        unwrap_fe(installer::install(local_data, |_a, _b| {}));
+        let installed = installer::install(local_data, |_a, _b| {});
+        if let Err(re) = &installed {
+            if re.is_out_of_space {
+                error!("{}", re.message());
+                eprintln!("{}", re.message());
+                common::exit(1);
+            }
+        }
+        unwrap_fe(installed);
         //common::execute_program_and_terminate(&local_data);
 
         common::exit(0);
@@ -103,9 +102,37 @@ fn should_app_update(local_data: &mut StoredInstallData) -> bool {
             info!("Nothing to be done, launching.");
             return false;
         }
+        let forced = common::arg_value_set(defines::PAKKLY_CLI_INSTALLEXACT_APP).is_some();
+        if !forced && cloned.skipped_app_version == Some(potential_date) {
+            info!("Version was rolled back, waiting for a newer one.");
+            return false;
+        }
     }
     return true;
 }
+/// An interrupted install that cannot be dealt with now is never taken for a fresh install,
+/// the installed app is launched as it is and the next launch tries again.
+fn interrupted_install_hook() {
+    let interrupted = match StoredInstallData::read_json_unchecked() {
+        Ok(x) if x.installing => x,
+        _ => return,
+    };
+    //another shipper might be in the middle of that very install.
+    if ipc::other_running().unwrap_or(true) {
+        warn!("Interrupted install found while the app is running, resuming it on a later launch.");
+        common::execute_program_and_terminate(interrupted);
+    }
+    let resumed = installer::resume_interrupted_install();
+    if resumed.is_err() {
+        error!("Could not resume interrupted install!");
+        error!("{:?}", resumed.unwrap_err());
+        if let Ok(stored) = StoredInstallData::read_json_unchecked() {
+            if stored.installing {
+                common::execute_program_and_terminate(stored);
+            }
+        }
+    }
+}
 fn uninstall_exit_hook(local_data: Option<&mut StoredInstallData>) {
     let quiet = common::arg_flag_set(defines::PAKKLY_CLI_UNINSTALL_QUIET);
     let regular = common::arg_flag_set(defines::PAKKLY_CLI_UNINSTALL);
@@ -119,6 +146,84 @@ fn uninstall_exit_hook(local_data: Option<&mut StoredInstallData>) {
         common::exit(0); //actually is never called since uninstall does that itself. Just a compiler hint
     }
 }
+fn verify_exit_hook(local_data: Option<&StoredInstallData>) {
+    if common::arg_flag_set(defines::PAKKLY_CLI_VERIFY) {
+        if local_data.is_none() {
+            error!("Verification triggered without installation present.");
+            eprintln!("Not installed.");
+            common::exit(1);
+        }
+        let report = verifier::verify_installation(local_data.unwrap());
+        if report.is_err() {
+            error!("{:?}", report.unwrap_err());
+            eprintln!("Verification failed, see the log for details.");
+            common::exit(1);
+        }
+        let report = report.unwrap();
+        if common::arg_flag_set(defines::PAKKLY_CLI_JSON) {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            print!("{}", report.to_human());
+        }
+        common::exit(report.exit_code());
+    }
+}
+fn repair_exit_hook(local_data: &mut StoredInstallData) {
+    if common::arg_flag_set(defines::PAKKLY_CLI_REPAIR) {
+        let report = unwrap_fe(verifier::verify_installation(local_data));
+        let damaged = |x: &String| report.missing.contains(x) || report.modified.contains(x);
+        let shipperfile = local_data.shipperfile.clone();
+        //preserved files are expected to change, only restore them when they are gone.
+        let damaged_files: HashSet<PathBuf> = local_data
+            .installed_files
+            .iter()
+            .filter(|x| match shipperfile.as_ref().map_or(false, |s| s.is_preserved(&x.dst_path)) {
+                true => report.missing.contains(&x.dst_path_human),
+                false => damaged(&x.dst_path_human),
+            })
+            .map(|x| x.dst_path.clone())
+            .collect();
+        let damaged_meta = local_data.installed_files_meta.iter().filter(|x| damaged(&x.dst_path_human)).count();
+        if damaged_files.is_empty() && damaged_meta == 0 {
+            println!("Nothing to repair.");
+            common::exit(0);
+        }
+        if damaged_files.is_empty() {
+            //meta files are generated, no download needed. A repair regenerates them as well.
+            unwrap_fe(installer_tools::replace_meta_files(local_data));
+        } else {
+            let version = local_data.installed_app_info.version.clone();
+            let fetched = common::get_update_info(Some(local_data), Some(version), None);
+            if fetched.is_err() {
+                error!("{:?}", fetched.unwrap_err());
+                eprintln!("Server could not be reached or returned malformed response!");
+                common::exit(1);
+            }
+            local_data.fetched_meta = fetched.unwrap();
+            let repaired = installer::repair(local_data, &damaged_files, |_a, _b| {});
+            if repaired.is_err() {
+                error!("{:?}", repaired.unwrap_err());
+                eprintln!("Repair failed, see the log for details.");
+                common::exit(1);
+            }
+        }
+        println!("Repaired {} files.", damaged_files.len() + damaged_meta);
+        common::exit(0);
+    }
+}
+fn rollback_exit_hook(local_data: &mut StoredInstallData) {
+    if common::arg_flag_set(defines::PAKKLY_CLI_ROLLBACK) {
+        let from = local_data.installed_app_info.version.clone();
+        let rolled_back = installer::rollback(local_data);
+        if rolled_back.is_err() {
+            error!("{:?}", rolled_back.unwrap_err());
+            eprintln!("Rollback failed, see the log for details.");
+            common::exit(1);
+        }
+        println!("Rolled back from {} to {}", from, local_data.installed_app_info.version);
+        common::exit(0);
+    }
+}
 fn force_update_hook(local_data: &mut StoredInstallData) {
     let specific_app = common::arg_value_set(defines::PAKKLY_CLI_INSTALLEXACT_APP);
     let specific_shipper = common::arg_value_set(defines::PAKKLY_CLI_INSTALLEXACT_SHIPPER);
@@ -159,7 +264,12 @@ fn duplicate_process_hook(local_data: &mut StoredInstallData) {
         let regular = common::arg_flag_set(defines::PAKKLY_CLI_UNINSTALL);
         if quiet || regular {
             error!("Cannot uninstall while program is running!");
-            webview_alert::alert("Program already running.", "Cannot uninstall while program is running!", None);
+            frontend::alert("Program already running.", "Cannot uninstall while program is running!", None);
+            common::exit(1);
+        }
+        if common::arg_flag_set(defines::PAKKLY_CLI_ROLLBACK) || common::arg_flag_set(defines::PAKKLY_CLI_REPAIR) {
+            error!("Cannot modify the installation while program is running!");
+            eprintln!("Cannot modify the installation while program is running!");
             common::exit(1);
         }
         info!("Found duplicate shipper of this app_id. Won't check for updates this run.");
@@ -192,22 +302,33 @@ fn main() {
     let _ipc = &*defines::IPC_INFO;
     prints_exit_hooks();
     log::set_logger(&*common::LOGGER).map(|()| log::set_max_level(log::LevelFilter::Debug)).unwrap();
+    //picked before the hook below, which may need it to ask about sending the error.
+    let _frontend = &*defines::FRONTEND;
     std::panic::set_hook(Box::new(|pi| {
-        emit_panic_error(&pi);
+        common::emit_panic_error(&pi);
 
         //execute directly as we are guaranteed to be in the main thread:
         common::submit_critical_error();
         common::exit(1);
     }));
 
+    interrupted_install_hook();
+
     info!("Install mode active: {}", *defines::FRESH_INSTALL);
 
     let mut local_data: StoredInstallData;
 
     if *defines::FRESH_INSTALL {
         uninstall_exit_hook(None);
+        verify_exit_hook(None);
         let mut new_data;
         loop {
+            if let Some(payload) = &*defines::EMBEDDED_PAYLOAD {
+                //offline installer, everything needed is already here.
+                info!("Installing from the embedded payload.");
+                new_data = Ok(payload.meta.clone());
+                break;
+            }
             new_data = common::get_update_info(None, None, None);
             if new_data.is_err() {
                 let install_quiet = common::arg_flag_set(defines::PAKKLY_CLI_INSTALL_QUIET);
@@ -215,11 +336,11 @@ fn main() {
                     error!("Server could not be reached or returned malformed response!");
                     common::exit(1);
                 }
-                let retry = webview_alert::confirm(ConfirmParams {
+                let retry = frontend::confirm(ConfirmParams {
                     title: "No Connection".into(),
                     body: "The server could not be reached, please check your internet connection and try again."
                         .into(),
-                    image: webview_alert::ConfirmImage::NoInternet,
+                    image: frontend::ConfirmImage::NoInternet,
                     no_str: "Cancel".into(),
                     yes_str: "Retry".into(),
                 });
@@ -234,10 +355,16 @@ fn main() {
     } else {
         local_data = unwrap_fe(StoredInstallData::read_json());
 
+        verify_exit_hook(Some(&local_data));
+
         duplicate_process_hook(&mut local_data);
 
         uninstall_exit_hook(Some(&mut local_data));
 
+        rollback_exit_hook(&mut local_data);
+
+        repair_exit_hook(&mut local_data);
+
         install_indirect_exit_hook(&mut local_data);
 
         update_timer_exit_hook(&mut local_data);
@@ -265,73 +392,24 @@ fn main() {
     } else {
         install_quiet_exit_hook(&mut local_data);
         if should_app_update(&mut local_data) {
-            let html_content = html_embed::UPDATER_UI;
-            let should_download = Arc::new(Mutex::new(false));
-            let thread_should = should_download.clone();
+            let space_message = Arc::new(Mutex::new(String::new()));
+            let thread_space_message = space_message.clone();
             let local_data_copy = local_data.clone();
 
-            let wv = webview::Webview::new(html_content, &local_data, || {
-                *should_download.lock().unwrap() = true;
-            });
-            let handle = wv.create_handle();
-            let handle_mutex_t1 = Arc::new(Mutex::new(handle));
-            let handle_mutex_t2 = handle_mutex_t1.clone();
-            let handle_mutex_t3 = handle_mutex_t1.clone();
-            let handle_mutex_panic = handle_mutex_t1.clone();
-            let handle_mutex_netcrash = handle_mutex_t1.clone();
-            std::panic::set_hook(Box::new(move |pi| {
-                emit_panic_error(&pi);
-
-                let h = handle_mutex_panic.lock().unwrap();
-                let attempt = h.dispatch(move |a| {
-                    *a.user_data_mut() = CrashState::FatalError;
-                    a.exit();
-                    return Ok(());
-                });
-                if attempt.is_err() {
-                    //try one last time
-                    common::submit_critical_error();
-                    common::exit(1);
-                }
-            }));
-
-            thread::spawn(move || loop {
-                if *thread_should.lock().unwrap() {
-                    info!("Threaded download starting!");
-                    let install_status = installer::install(&mut local_data, move |progress, segment| {
-                        webview::Webview::set_download_progress(&handle_mutex_t2.lock().unwrap(), segment, progress);
-                    });
-                    let mut cs = CrashState::NoError;
-                    if install_status.is_err() {
-                        let re = install_status.err().unwrap();
-                        error!("{:?}", re);
-                        if re.is_network_error {
-                            cs = CrashState::NetworkError;
-                        } else {
-                            cs = CrashState::InstallFailed;
-                        }
-                    }
-                    if *defines::FRESH_INSTALL {
-                        thread::sleep(Duration::from_millis(300));
-                        webview::Webview::set_download_progress(
-                            &handle_mutex_t3.lock().unwrap(),
-                            common::InstallProgressSegment::Installing,
-                            1.0,
-                        );
-                        thread::sleep(Duration::from_millis(1500));
+            let val = frontend::run_install(&local_data_copy, move |progress| {
+                let install_status = installer::install(&mut local_data, progress);
+                if let Err(re) = install_status {
+                    error!("{:?}", re);
+                    if re.is_network_error {
+                        return CrashState::NetworkError;
+                    } else if re.is_out_of_space {
+                        *thread_space_message.lock().unwrap() = re.message();
+                        return CrashState::OutOfSpace;
                     }
-                    let h = &handle_mutex_netcrash.lock().unwrap();
-                    h.dispatch(move |a| {
-                        *a.user_data_mut() = cs;
-                        a.exit();
-                        return Ok(());
-                    })
-                    .unwrap();
-                    break;
+                    return CrashState::InstallFailed;
                 }
-                thread::sleep(Duration::from_millis(100));
+                return CrashState::NoError;
             });
-            let val = wv.run().unwrap();
 
             if val == CrashState::UserAbort {
                 common::exit(0);
@@ -346,7 +424,7 @@ fn main() {
                 }
             }
             if val == CrashState::NetworkError {
-                webview_alert::alert(
+                frontend::alert(
                     "Connection Interrupted",
                     "The server could not be reached, please check your internet connection and try again.",
                     None,
@@ -356,6 +434,13 @@ fn main() {
                 }
                 common::exit(1);
             }
+            if val == CrashState::OutOfSpace {
+                frontend::alert("Not Enough Disk Space", space_message.lock().unwrap().as_str(), None);
+                if !(*defines::FRESH_INSTALL) {
+                    common::execute_program_and_terminate(local_data_copy);
+                }
+                common::exit(1);
+            }
             if val == CrashState::NoError {
                 //workaround for the fact that the shipperfile is not set yet during the first install.
                 let freshest_data = unwrap_fe(StoredInstallData::read_json());
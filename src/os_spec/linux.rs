@@ -1,10 +1,15 @@
 use crate::fslog;
+use crate::shipperfile::{MimeDefinition, Shipperfile};
 use log::info;
-use pakkly_error::FormattedError;
+use pakkly_error::{ferror, FormattedError};
 use std::io::{self, Write};
+use std::path::PathBuf;
 use std::process::Command;
 use tempfile::TempDir;
 
+pub static XDG_APPLICATIONS_DIR: &str = "/usr/share/applications";
+pub static XDG_MIME_DIR: &str = "/usr/share/mime";
+
 struct SudoCommand {
     command: String,
     args: Vec<String>,
@@ -38,6 +43,9 @@ impl LinuxSudo {
     pub fn command(&mut self, command: String, args: Vec<String>) {
         self.cmd(command, args, None);
     }
+    pub fn is_empty(&self) -> bool {
+        return self.commands_buffer.len() == 0;
+    }
     pub fn flush(&self) -> Result<(), FormattedError> {
         if self.commands_buffer.len() == 0 {
             return Err(FormattedError::from_str("Sudo cmdbuffer cannot be length 0".to_string()));
@@ -76,3 +84,196 @@ impl LinuxSudo {
         return Err(FormattedError::from_missing_sudo("No Sudo Found".to_string()));
     }
 }
+
+/// All MIME types the desktop entry should declare, URL schemes are registered as x-scheme-handler/<scheme>.
+pub fn desktop_mime_types(shipperfile: &Shipperfile) -> Vec<String> {
+    let mut ret: Vec<String> = vec![];
+    if let Some(mime_types) = &shipperfile.mime_types {
+        ret.extend(mime_types.iter().cloned());
+    }
+    if let Some(mime_definitions) = &shipperfile.mime_definitions {
+        for definition in mime_definitions {
+            if !ret.contains(&definition.mime_type) {
+                ret.push(definition.mime_type.clone());
+            }
+        }
+    }
+    if let Some(url_schemes) = &shipperfile.url_schemes {
+        for scheme in url_schemes {
+            ret.push(format!("x-scheme-handler/{}", scheme.trim_end_matches("://").to_lowercase()));
+        }
+    }
+    return ret;
+}
+/// Builds a shared-mime-info package, to be placed in /usr/share/mime/packages.
+pub fn mime_package_xml(definitions: &Vec<MimeDefinition>) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n",
+    );
+    for definition in definitions {
+        xml += &format!("  <mime-type type=\"{}\">\n", xml_escape(&definition.mime_type));
+        if let Some(comment) = &definition.comment {
+            xml += &format!("    <comment>{}</comment>\n", xml_escape(comment));
+        }
+        for pattern in &definition.glob_patterns {
+            xml += &format!("    <glob pattern=\"{}\"/>\n", xml_escape(pattern));
+        }
+        xml += "  </mime-type>\n";
+    }
+    xml += "</mime-info>\n";
+    return xml;
+}
+fn xml_escape(value: &str) -> String {
+    return value
+        .replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;");
+}
+/// Looks up one of the XDG database tools (update-mime-database, update-desktop-database) in the PATH.
+pub fn find_xdg_tool(name: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH").unwrap_or("/usr/local/bin:/usr/bin:/bin".into());
+    for dir in std::env::split_paths(&path_var) {
+        let candidate = dir.join(name);
+        if fslog::exists(&candidate) {
+            return Some(candidate);
+        }
+    }
+    return None;
+}
+/// Queues the commands that make XDG pick up changed desktop entries and MIME packages.
+pub fn queue_xdg_refresh(linsudo: &mut LinuxSudo) {
+    if let Some(tool) = find_xdg_tool("update-mime-database") {
+        linsudo.command(tool.to_string_lossy().to_string(), [XDG_MIME_DIR.to_string()].to_vec());
+    }
+    if let Some(tool) = find_xdg_tool("update-desktop-database") {
+        linsudo
+            .command(tool.to_string_lossy().to_string(), ["-q".to_string(), XDG_APPLICATIONS_DIR.to_string()].to_vec());
+    }
+}
+/// Refreshes the XDG databases directly, used during uninstall where we already run as root.
+pub fn refresh_xdg_databases() {
+    if let Some(tool) = find_xdg_tool("update-mime-database") {
+        let status = Command::new(tool).arg(XDG_MIME_DIR).status();
+        info!("update-mime-database: {:?}", status);
+    }
+    if let Some(tool) = find_xdg_tool("update-desktop-database") {
+        let status = Command::new(tool).arg("-q").arg(XDG_APPLICATIONS_DIR).status();
+        info!("update-desktop-database: {:?}", status);
+    }
+}
+/// Activates the top-level window owned by the PID through EWMH (_NET_WM_PID, _NET_ACTIVE_WINDOW).
+/// Only X11 is supported, Wayland does not allow other clients to take focus.
+pub fn focus_app_by_pid(pid: i64) -> Result<(), FormattedError> {
+    use x11rb::{
+        connection::Connection,
+        protocol::xproto::{AtomEnum, ClientMessageEvent, ConnectionExt, EventMask},
+    };
+    if std::env::var_os("DISPLAY").is_none() {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            return Err(ferror!("Cannot focus windows under Wayland without XWayland."));
+        }
+        return Err(ferror!("No X11 display to focus on."));
+    }
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let net_client_list = conn.intern_atom(false, b"_NET_CLIENT_LIST")?.reply()?.atom;
+    let net_wm_pid = conn.intern_atom(false, b"_NET_WM_PID")?.reply()?.atom;
+    let net_active_window = conn.intern_atom(false, b"_NET_ACTIVE_WINDOW")?.reply()?.atom;
+
+    let client_list = conn.get_property(false, root, net_client_list, AtomEnum::WINDOW, 0, u32::MAX)?.reply()?;
+    let windows: Vec<u32> = match client_list.value32() {
+        Some(x) => x.collect(),
+        None => return Err(ferror!("Window manager does not support _NET_CLIENT_LIST.")),
+    };
+    for window in windows {
+        let reply = conn.get_property(false, window, net_wm_pid, AtomEnum::CARDINAL, 0, 1).map(|x| x.reply());
+        let window_pid = match reply {
+            Ok(Ok(x)) => x,
+            //the client list can name windows that are gone by now, they are no reason to give up.
+            _ => {
+                info!("Skipping window {}, its _NET_WM_PID could not be read.", window);
+                continue;
+            }
+        };
+        let matches = window_pid.value32().and_then(|mut x| x.next()).map(|x| i64::from(x) == pid).unwrap_or(false);
+        if !matches {
+            continue;
+        }
+        //source indication 2 = pager, which window managers do not apply focus stealing prevention to.
+        let event = ClientMessageEvent::new(32, window, net_active_window, [2, x11rb::CURRENT_TIME, 0, 0, 0]);
+        conn.send_event(false, root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
+        conn.flush()?;
+        return Ok(());
+    }
+    return Err(ferror!("Focus target not found!"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::focus_app_by_pid;
+    use std::time::{Duration, Instant};
+    use x11rb::{
+        connection::Connection,
+        protocol::{xproto::*, Event},
+        wrapper::ConnectionExt as _,
+        COPY_DEPTH_FROM_PARENT,
+    };
+
+    /// Plays the window manager on a bare X server: publishes _NET_CLIENT_LIST with a window that no longer exists
+    /// ahead of ours and waits for the _NET_ACTIVE_WINDOW request. Run with `xvfb-run cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs an X server without a window manager, such as Xvfb"]
+    fn focus_skips_vanished_windows() {
+        let (conn, screen_num) = x11rb::connect(None).expect("no X server, run under xvfb-run");
+        let screen = &conn.setup().roots[screen_num];
+        let root = screen.root;
+        let atom = |name: &[u8]| conn.intern_atom(false, name).unwrap().reply().unwrap().atom;
+        let (net_client_list, net_wm_pid, net_active_window) =
+            (atom(b"_NET_CLIENT_LIST"), atom(b"_NET_WM_PID"), atom(b"_NET_ACTIVE_WINDOW"));
+        let new_window = || {
+            let window = conn.generate_id().unwrap();
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                10,
+                10,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &CreateWindowAux::new(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+            return window;
+        };
+        let gone = new_window();
+        conn.destroy_window(gone).unwrap().check().unwrap();
+        let ours = new_window();
+        let pid = std::process::id();
+        conn.change_property32(PropMode::REPLACE, ours, net_wm_pid, AtomEnum::CARDINAL, &[pid]).unwrap();
+        conn.change_property32(PropMode::REPLACE, root, net_client_list, AtomEnum::WINDOW, &[gone, ours]).unwrap();
+        let redirect = ChangeWindowAttributesAux::new().event_mask(EventMask::SUBSTRUCTURE_REDIRECT);
+        conn.change_window_attributes(root, &redirect).unwrap().check().expect("another window manager is running");
+
+        focus_app_by_pid(i64::from(pid)).unwrap();
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            match conn.poll_for_event().unwrap() {
+                Some(Event::ClientMessage(event)) if event.type_ == net_active_window => {
+                    assert_eq!(event.window, ours);
+                    return;
+                }
+                Some(_) => {}
+                None => std::thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        panic!("no _NET_ACTIVE_WINDOW request arrived");
+    }
+}
@@ -36,6 +36,14 @@ pub fn get_ipc_dir() -> PathBuf {
 pub fn get_install_path() -> PathBuf {
     return get_install_subdir("program");
 }
+/// Updates are extracted here, on the same volume as the install so they survive a crash.
+pub fn get_install_staging_dir() -> PathBuf {
+    return get_install_subdir("staging");
+}
+/// Files the last update replaced or removed, kept for --pakkly_rollback.
+pub fn get_install_previous_dir() -> PathBuf {
+    return get_install_subdir("previous");
+}
 pub fn get_install_dir_pakkly() -> PathBuf {
     return get_install_subdir("runner");
 }
@@ -70,6 +78,13 @@ pub fn get_install_file_pakkly(parameters: &StoredInstallData) -> PathBuf {
     return dest.join(format!("{}{}", app_name, extension));
 }
 pub fn get_install_root() -> PathBuf {
+    #[cfg(debug_assertions)]
+    {
+        //sandboxed, a debug build should not touch a real install.
+        if let Some(root) = &crate::config::DEBUG_OVERRIDES.install_root {
+            return root.join(&["pakked_", (*PAKKLY_ID_CLEAN).as_str()].concat());
+        }
+    }
     let dest = BaseDirs::new().unwrap();
     let destination = dest.data_local_dir().join(&["pakked_", (*PAKKLY_ID_CLEAN).as_str()].concat());
     return destination;
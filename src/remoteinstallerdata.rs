@@ -27,6 +27,20 @@ pub struct StoredInstallData {
     pub installing: bool,
     pub shipperfile: Option<Shipperfile>,
     pub installed_app_info: DownloadParams,
+    //set while installing, allows an interrupted update to be finished on the next launch.
+    #[serde(default)]
+    pub pending_install: Option<PendingInstall>,
+    //the version replaced by the last update, its changed and removed files are kept in the previous dir.
+    #[serde(default)]
+    pub previous_version: Option<RetainedVersion>,
+    //a rolled back version, not offered again until the server publishes a different one.
+    #[serde(default)]
+    pub skipped_app_version: Option<String>,
+    //set after an update until the app survives a launch, see common::record_launch_health.
+    #[serde(default)]
+    pub health_pending: bool,
+    #[serde(default)]
+    pub failed_launches: u32,
 }
 impl StoredInstallData {
     pub fn from(o: PakklyMetaRemote) -> Result<StoredInstallData, FormattedError> {
@@ -41,6 +55,11 @@ impl StoredInstallData {
             last_ucheck: 0,
             installing: false,
             shipperfile: None,
+            pending_install: None,
+            previous_version: None,
+            skipped_app_version: None,
+            health_pending: false,
+            failed_launches: 0,
         });
     }
     pub fn write_json(&self) -> Result<(), FormattedError> {
@@ -56,23 +75,87 @@ impl StoredInstallData {
             return Err(FormattedError::from_str("Deferred hashes must be fulfilled before writing!".to_string()));
         }
         let json = serde_json::to_string(&self)?;
-        fslog::write(paths::get_json_path(), json)?;
+        //written aside and renamed, a full disk must not leave a truncated file behind.
+        let json_path = paths::get_json_path();
+        let json_path_tmp = json_path.with_extension("json.tmp");
+        fslog::write(&json_path_tmp, json)?;
+        fslog::rename(&json_path_tmp, &json_path)?;
         Ok(())
     }
     pub fn read_json() -> Result<StoredInstallData, FormattedError> {
+        let rid = Self::read_json_unchecked()?;
+        if rid.installing {
+            return Err(FormattedError::from_str("Install corrupted".to_string()));
+        }
+        return Ok(rid);
+    }
+    /// Also returns installs that were interrupted mid-update, only meant for recovering them.
+    pub fn read_json_unchecked() -> Result<StoredInstallData, FormattedError> {
         let path: PathBuf = PathBuf::from(paths::get_json_path());
         if fslog::exists(&path) {
             let file_data = fslog::read_to_string(path)?;
             let rid: StoredInstallData = serde_json::from_str(&file_data)?;
-            if rid.installing {
-                return Err(FormattedError::from_str("Install corrupted".to_string()));
-            }
             return Ok(rid);
         } else {
             return Err(FormattedError::from_str("File not found!".to_string()));
         }
     }
 }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InstallPhase {
+    //new files are being placed next to their targets, the install is untouched
+    Staging,
+    //targets are being swapped with the staged files, undone by replaying the journal
+    Swapping,
+    //all files are in place, obsolete ones and backups are being removed
+    Cleanup,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateFileInfo {
+    pub from_path: String,
+    pub to_path: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub target: String,
+    pub staged: String,
+    //where the previous version of target is kept until the update commits, None if target is new.
+    pub backup: Option<String>,
+    //a file of the installed version that becomes a directory, moved aside before staging so files can be staged below it.
+    #[serde(default)]
+    pub directory: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingInstall {
+    pub phase: InstallPhase,
+    pub staging_dir: PathBuf,
+    pub journal: Vec<JournalEntry>,
+    //the installed_files list once the update completes, hashes may still be deferred.
+    pub new_files: Vec<InstalledFile>,
+    pub app_info: DownloadParams,
+    pub shipperfile: Option<Shipperfile>,
+    //repairs restore the installed version, they never replace the retained previous version.
+    #[serde(default)]
+    pub repair: bool,
+    //the version retained before this update is gone, previous/ only holds what this update replaced.
+    #[serde(default)]
+    pub previous_dropped: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetainedVersion {
+    pub app_info: DownloadParams,
+    pub installed_files: Vec<InstalledFile>,
+    pub shipperfile: Option<Shipperfile>,
+}
+impl RetainedVersion {
+    pub fn of(install: &StoredInstallData) -> RetainedVersion {
+        return RetainedVersion {
+            app_info: install.installed_app_info.clone(),
+            installed_files: install.installed_files.clone(),
+            shipperfile: install.shipperfile.clone(),
+        };
+    }
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PakklyMetaRemote {
     pub background_color: String,
@@ -111,17 +194,32 @@ impl TryFrom<&str> for InstallerColor {
         }
     }
 }
+/// How InstalledFile::hash was made. Entries written before hashes were tagged are WyHash,
+/// updates rehash them with BLAKE3 as they go.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    wyhash,
+    blake3,
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InstalledFile {
     pub dst_path: PathBuf,
     pub dst_path_human: String,
     pub hash: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
     pub size: u64,
     pub root: Option<PathBuf>,
+    //where the symlink points, as stored in the link. None for everything else.
+    #[serde(default)]
+    pub link_target: Option<PathBuf>,
 }
 pub struct FileContentsMeta {
     pub hash: String,
     pub size: u64,
+    pub link_target: Option<PathBuf>,
 }
 
 impl InstalledFile {
@@ -135,19 +233,23 @@ impl InstalledFile {
         let fcm = Self::size_and_hash(&absolute)?;
         self.size = fcm.size;
         self.hash = fcm.hash;
+        self.hash_algorithm = HashAlgorithm::blake3;
+        self.link_target = fcm.link_target;
         Ok(())
     }
     fn size_and_hash(absolute: &PathBuf) -> Result<FileContentsMeta, FormattedError> {
         let hash: String;
         let size: u64;
+        let mut link_target = None;
         let meta = std::fs::symlink_metadata(&absolute);
         if meta.is_err() {
             return Err(FormattedError::from_str(format!("Non-existant file added to db: {:?}", absolute)));
         }
         let muw = meta.unwrap();
         if muw.is_symlink() {
-            hash = defines::HASH_ALWAYS_REPLACE.to_string();
+            hash = defines::HASH_SYMLINK.to_string();
             size = 0;
+            link_target = Some(std::fs::read_link(&absolute)?);
         } else {
             if muw.is_dir() {
                 hash = defines::HASH_DIRECTORY.to_string();
@@ -161,19 +263,12 @@ impl InstalledFile {
                 }
                 hash = match is_hash_whitelisted(&absolute) {
                     true => defines::HASH_ALWAYS_REPLACE.to_string(),
-                    false => {
-                        let (fh_num, fh_bytes) = common::get_file_hash(&absolute)?;
-                        if fh_num == 0 {
-                            defines::HASH_DEFER.to_string()
-                        } else {
-                            hex::encode(fh_bytes)
-                        }
-                    }
+                    false => hex::encode(common::get_file_hash(&absolute)?),
                 };
                 size = muw.len();
             }
         }
-        Ok(FileContentsMeta { size, hash })
+        Ok(FileContentsMeta { size, hash, link_target })
     }
     pub fn new_rooted_precontent<P: AsRef<Path>>(
         file_path: &P,
@@ -184,7 +279,9 @@ impl InstalledFile {
             dst_path: file_path.as_ref().to_path_buf(),
             dst_path_human: file_path.as_ref().as_os_str().to_string_lossy().to_string(),
             hash: content_meta.hash,
+            hash_algorithm: HashAlgorithm::blake3,
             size: content_meta.size,
+            link_target: content_meta.link_target,
             root: match root {
                 Some(r) => Some(r.as_ref().to_path_buf()),
                 None => None,
@@ -210,4 +307,34 @@ impl InstalledFile {
 pub struct DownloadParams {
     pub url: String,
     pub version: String,
+    //BLAKE3 of every file in the archive, when the server publishes it.
+    #[serde(default)]
+    pub manifest: Option<Vec<ManifestEntry>>,
+    #[serde(default)]
+    pub archive_limits: ArchiveLimits,
+}
+/// What a downloaded archive may expand to, checked before and while extracting.
+/// The server can loosen these for unusually large apps, missing fields keep the defaults.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ArchiveLimits {
+    pub max_uncompressed_bytes: u64,
+    pub max_entries: u64,
+    //uncompressed over compressed size, for every entry and the archive as a whole
+    pub max_compression_ratio: u64,
+}
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        return ArchiveLimits {
+            max_uncompressed_bytes: defines::ARCHIVE_MAX_UNCOMPRESSED_BYTES,
+            max_entries: defines::ARCHIVE_MAX_ENTRIES,
+            max_compression_ratio: defines::ARCHIVE_MAX_COMPRESSION_RATIO,
+        };
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    //relative to the program directory, always separated by "/"
+    pub path: String,
+    pub blake3: String,
 }
@@ -1,6 +1,7 @@
+use crate::embedded::EmbeddedPayload;
 use crate::remoteinstallerdata::StoredInstallData;
-use crate::{common, fslog, paths};
-use crate::{installer_tools, unzip};
+use crate::{archive, installer_tools};
+use crate::{common, defines, fslog, paths};
 use log::info;
 use pakkly_error::FormattedError;
 use std::fs;
@@ -10,12 +11,6 @@ use tempfile::tempdir;
 #[cfg(target_os = "windows")]
 use crate::common::execute_detached;
 
-#[cfg(target_os = "windows")]
-use crate::defines;
-
-#[cfg(target_os = "linux")]
-use crate::common::untar;
-
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
@@ -71,50 +66,71 @@ pub fn install_shipper(destination_path: &PathBuf, params: &mut StoredInstallDat
     let tmpdir = tempdir()?;
     let mut tmpfilepath = tmpdir.path().to_path_buf();
     tmpfilepath.push("temporary_download_pakkly");
-    common::download_file(&params.fetched_meta.shipper.url, &tmpfilepath, |_a, _b| {})?;
+    match EmbeddedPayload::shipper_for(&params.fetched_meta.shipper.version) {
+        Some(payload) => payload.write_shipper(&tmpfilepath)?,
+        None => common::download_file(&params.fetched_meta.shipper.url, &tmpfilepath, &[], |_a, _b| {})?,
+    }
 
     let zip_tmpdir = tempdir()?;
     let unzip_path = zip_tmpdir.path().to_path_buf();
-    if unzip::is_file_zip(&tmpfilepath)? {
-        info!("Zip detected! Unzipping to {:?}", unzip_path);
-        unzip::extract(&tmpfilepath, &unzip_path, |_a, _b| {})?;
+    if let Some(format) = archive::detect_format(&tmpfilepath)? {
+        info!("{:?} detected! Extracting to {:?}", format, unzip_path);
+        archive::extract(&tmpfilepath, &unzip_path, &params.fetched_meta.shipper.archive_limits, |_a, _b| {})?;
     } else {
-        #[cfg(target_os = "linux")]
-        {
-            untar(&tmpfilepath, &unzip_path)?;
-        }
-        #[cfg(not(target_os = "linux"))]
-        {
-            let final_dir = zip_tmpdir.path().join("a_file");
-            fslog::copy(&tmpfilepath, &final_dir)?;
-        }
+        let final_dir = zip_tmpdir.path().join("a_file");
+        fslog::copy(&tmpfilepath, &final_dir)?;
     }
     {
         let dest_path_with_filename = Path::new(&destination_path).to_path_buf();
         let dest_path_folder = dest_path_with_filename.parent().unwrap().to_path_buf();
-        if fslog::exists(&dest_path_folder) {
-            info!("Deleting the previous install: {:?}", dest_path_folder);
-            fslog::remove_dir_all(&dest_path_folder)?;
-        }
         fslog::create_dir_all(&dest_path_folder)?;
-
-        fslog::copy_dir(&unzip_path, &dest_path_folder)?;
-
-        #[cfg(unix)]
-        {
-            let all_files = common::all_relative_files_in_folder_recursive(&dest_path_folder)?;
-            for file in all_files {
-                fs::set_permissions(dest_path_folder.join(file), fs::Permissions::from_mode(0o744))?
+        remove_stale_runner_files(&dest_path_folder, &dest_path_with_filename, params)?;
+
+        //modes, mtimes and symlinks come from the archive as they are.
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.resize(defines::FS_BUFFER_SIZE, 0x00);
+        for file in common::all_relative_files_in_folder_recursive(&unzip_path)? {
+            let destination = dest_path_folder.join(&file);
+            if let Some(parent) = destination.parent() {
+                fslog::create_dir_all(parent)?;
             }
+            common::copy_file_preserving(unzip_path.join(&file), &destination, &mut buffer)?;
         }
 
         let original_filename = common::find_shipper_in_folder(&unzip_path)?.unwrap();
         let src_filename = Path::new(&original_filename).file_name().unwrap().to_str().unwrap();
         let wrong_filename = dest_path_folder.join(src_filename);
 
-        fslog::rename(wrong_filename, dest_path_with_filename)?;
+        fslog::rename(wrong_filename, &dest_path_with_filename)?;
+        #[cfg(unix)]
+        {
+            let mode = fs::metadata(&dest_path_with_filename)?.permissions().mode();
+            fs::set_permissions(&dest_path_with_filename, fs::Permissions::from_mode(mode | 0o744))?;
+        }
     }
 
     info!("Done!");
     return Ok(());
 }
+/// Clears the shipper folder for a new shipper. pakkly.installer.json, the ipc locks and the meta files live there too
+/// and are kept, as is the running executable unless it is the one being replaced: unlinked rather than overwritten.
+fn remove_stale_runner_files(folder: &Path, replaced: &Path, params: &StoredInstallData) -> Result<(), FormattedError> {
+    let mut kept = vec![paths::get_json_path(), paths::get_ipc_dir()];
+    kept.extend(params.installed_files_meta.iter().map(|x| x.dst_path.clone()));
+    let running = std::env::current_exe().and_then(|x| x.canonicalize()).ok();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if kept.contains(&path) {
+            continue;
+        }
+        if path != replaced && running.is_some() && path.canonicalize().ok() == running {
+            continue;
+        }
+        info!("Deleting the previous install: {:?}", path);
+        match path.is_dir() && !path.is_symlink() {
+            true => fslog::remove_dir_all(&path)?,
+            false => fslog::remove_file(&path)?,
+        }
+    }
+    return Ok(());
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 #[allow(non_camel_case_types)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum InstanceMode {
@@ -10,6 +11,22 @@ pub struct ShipperfileGenerated {
     //base64 encoded string
     pub icon: String,
 }
+/// A custom MIME type, registered through the shared-mime-info database on Linux.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MimeDefinition {
+    pub mime_type: String,
+    pub comment: Option<String>,
+    //e.g. "*.proj"
+    pub glob_patterns: Vec<String>,
+}
+/// Watches the first launches after an update. An app that keeps failing within the window is rolled back.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HealthCheck {
+    //a launch that exits unsuccessfully within this many seconds counts as a failure.
+    pub window_seconds: Option<u64>,
+    //consecutive failures before rolling back, 0 disables the check.
+    pub max_failures: Option<u32>,
+}
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Shipperfile {
     pub app_id: String,
@@ -19,5 +36,27 @@ pub struct Shipperfile {
     pub program_arguments: Option<Vec<String>>,
     pub program_working_subdirectory: Option<String>,
     pub instance_mode: Option<InstanceMode>,
+    //MIME types the app can open, e.g. "application/pdf"
+    pub mime_types: Option<Vec<String>>,
+    //MIME types the app defines itself, these get registered along with the app.
+    pub mime_definitions: Option<Vec<MimeDefinition>>,
+    //URL schemes the app handles, without the "://", e.g. "myapp"
+    pub url_schemes: Option<Vec<String>>,
+    pub health_check: Option<HealthCheck>,
+    //files the user is expected to edit, e.g. shipped default configs. Repairs only restore them when missing.
+    //paths are relative to the program directory, a trailing "/" preserves a whole directory.
+    pub preserved_files: Option<Vec<String>>,
     pub _generated: ShipperfileGenerated,
 }
+impl Shipperfile {
+    pub fn is_preserved(&self, relative: &Path) -> bool {
+        let preserved = match &self.preserved_files {
+            Some(x) => x,
+            None => return false,
+        };
+        return preserved.iter().any(|entry| match entry.ends_with('/') {
+            true => relative.starts_with(entry.trim_end_matches('/')),
+            false => relative == Path::new(entry),
+        });
+    }
+}
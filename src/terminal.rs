@@ -0,0 +1,160 @@
+use crate::common::{self, CrashState, InstallProgressSegment};
+use crate::defines;
+use crate::frontend::{ConfirmImage, ConfirmParams};
+use crate::remoteinstallerdata::StoredInstallData;
+use log::info;
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+static BAR_WIDTH: usize = 30;
+
+/// Asks on stderr and reads the answer from stdin. Without interactive nothing is read,
+/// alerts are acknowledged and questions get the no_str answer.
+pub fn confirm(params: &ConfirmParams, interactive: bool) -> bool {
+    let marker = match params.image {
+        ConfirmImage::NoInternet | ConfirmImage::Error => "!",
+        ConfirmImage::Question => "?",
+    };
+    eprintln!("\n[{}] {}\n{}", marker, params.title, params.body);
+    if params.no_str.is_empty() {
+        if interactive {
+            eprint!("Press Enter to continue...");
+            read_answer();
+        }
+        return true;
+    }
+    if !interactive {
+        eprintln!("Answering {:?}, nobody is there to ask.", params.no_str);
+        return false;
+    }
+    loop {
+        eprint!("{} (y) / {} (n): ", params.yes_str, params.no_str);
+        let answer = match read_answer() {
+            Some(x) => x.to_lowercase(),
+            //stdin is gone, nobody can say yes.
+            None => return false,
+        };
+        if answer == "y" || answer == "yes" || answer == params.yes_str.to_lowercase() {
+            return true;
+        }
+        if answer == "n" || answer == "no" || answer == params.no_str.to_lowercase() {
+            return false;
+        }
+    }
+}
+fn read_answer() -> Option<String> {
+    let _e = std::io::stderr().flush();
+    let mut line = String::new();
+    return match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    };
+}
+
+/// The terminal take on frontend::run_install: a progress bar when interactive, a line per step otherwise.
+pub fn run_install<F>(local_data: &StoredInstallData, interactive: bool, install: F) -> CrashState
+where
+    F: FnOnce(&dyn Fn(f32, InstallProgressSegment)) -> CrashState + Send + 'static,
+{
+    let app_name = &local_data.fetched_meta.app_name;
+    //an unattended fresh install has to be asked for, picking headless on its own is no consent.
+    let install_asked_for = common::arg_flag_set(defines::PAKKLY_CLI_INSTALL_QUIET)
+        || common::arg_value_set(defines::PAKKLY_CLI_FRONTEND).as_deref() == Some("headless");
+    if *defines::FRESH_INSTALL && !interactive && !install_asked_for {
+        eprintln!(
+            "Not installing {}, nobody is there to confirm. Pass {} or {} headless to install anyway.",
+            app_name,
+            defines::PAKKLY_CLI_INSTALL_QUIET,
+            defines::PAKKLY_CLI_FRONTEND
+        );
+        return CrashState::UserAbort;
+    }
+    if *defines::FRESH_INSTALL && interactive {
+        let accepted = confirm(
+            &ConfirmParams {
+                title: format!("Install {}", app_name),
+                body: local_data.fetched_meta.description.clone().unwrap_or_default(),
+                image: ConfirmImage::Question,
+                yes_str: "Install".into(),
+                no_str: "Cancel".into(),
+            },
+            true,
+        );
+        if !accepted {
+            return CrashState::UserAbort;
+        }
+    }
+    eprintln!("{} {}...", if *defines::FRESH_INSTALL { "Installing" } else { "Updating" }, app_name);
+
+    //a panic of the install thread, or of the workers it runs, is reported by joining it, the main thread carries on.
+    //panics of the main thread itself still reach the hook main set up.
+    let previous_hook: Arc<dyn Fn(&std::panic::PanicHookInfo<'_>) + Sync + Send> = Arc::from(std::panic::take_hook());
+    let chained_hook = previous_hook.clone();
+    let main_thread = thread::current().id();
+    std::panic::set_hook(Box::new(move |pi| {
+        if thread::current().id() != main_thread {
+            common::emit_panic_error(pi);
+        } else {
+            chained_hook(pi);
+        }
+    }));
+    let (sender, receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        info!("Threaded download starting!");
+        return install(&move |progress, segment| {
+            let _e = sender.send((segment, progress));
+        });
+    });
+    //ends once the install is done with the sender.
+    let mut bar = ProgressBar { interactive, segment: None, percent: -1 };
+    for (segment, progress) in receiver {
+        bar.update(segment, progress);
+    }
+    bar.finish();
+    let cs = worker.join().unwrap_or(CrashState::FatalError);
+    std::panic::set_hook(Box::new(move |pi| previous_hook(pi)));
+    return cs;
+}
+
+struct ProgressBar {
+    interactive: bool,
+    segment: Option<InstallProgressSegment>,
+    //-1 while the size is unknown
+    percent: i32,
+}
+impl ProgressBar {
+    fn update(&mut self, segment: InstallProgressSegment, progress: f32) {
+        let percent = if progress < 0.0 { -1 } else { (progress.min(1.0) * 100.0) as i32 };
+        let new_segment = self.segment != Some(segment);
+        if !new_segment {
+            //headless output goes to logs, every 10% is plenty there.
+            let step = if self.interactive { 1 } else { 10 };
+            if percent / step == self.percent / step {
+                return;
+            }
+        } else if self.interactive && self.segment.is_some() {
+            eprintln!();
+        }
+        self.segment = Some(segment);
+        self.percent = percent;
+
+        let label = match segment {
+            InstallProgressSegment::Downloading => "Downloading",
+            InstallProgressSegment::Installing => "Installing",
+        };
+        let amount = if percent < 0 { "...".to_string() } else { format!("{}%", percent) };
+        if !self.interactive {
+            eprintln!("{} {}", label, amount);
+            return;
+        }
+        let filled = percent.max(0) as usize * BAR_WIDTH / 100;
+        eprint!("\r{:<12}[{}{}] {:>4}", label, "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), amount);
+        let _e = std::io::stderr().flush();
+    }
+    fn finish(&self) {
+        if self.interactive && self.segment.is_some() {
+            eprintln!();
+        }
+    }
+}
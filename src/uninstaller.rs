@@ -5,8 +5,8 @@ use crate::remoteinstallerdata::StoredInstallData;
 use crate::{
     common::is_hash_whitelisted,
     defines,
+    frontend::{self, ConfirmParams},
     remoteinstallerdata::InstalledFile,
-    webview_alert::{self, ConfirmParams},
 };
 use hex;
 use log::{info, trace, warn};
@@ -14,7 +14,7 @@ use pakkly_error::FormattedError;
 use std::fs;
 use std::path::PathBuf;
 
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", target_os = "linux"))]
 use crate::installer_tools;
 
 struct InstallerInfoPathed {
@@ -23,10 +23,10 @@ struct InstallerInfoPathed {
 }
 pub fn uninstall_procedure(local_data: &StoredInstallData, quiet: bool) -> Result<(), FormattedError> {
     if !quiet {
-        let confirm_uninstall = webview_alert::confirm(ConfirmParams {
+        let confirm_uninstall = frontend::confirm(ConfirmParams {
             title: "Confirm Uninstall".into(),
             body: format!("Are you sure you want to uninstall {}?", local_data.fetched_meta.app_name).into(),
-            image: webview_alert::ConfirmImage::Question,
+            image: frontend::ConfirmImage::Question,
             no_str: "No".into(),
             yes_str: "Yes".into(),
         });
@@ -57,6 +57,16 @@ pub fn uninstall_procedure(local_data: &StoredInstallData, quiet: bool) -> Resul
     }
     info!("Erasing dangling IPC items...");
     ipc::erase_all()?;
+    let staging_dir = crate::paths::get_install_staging_dir();
+    if fslog::exists(&staging_dir) {
+        info!("Erasing leftover staging directory...");
+        common::warn_unwrap(fslog::remove_dir_all(&staging_dir));
+    }
+    let previous_dir = crate::paths::get_install_previous_dir();
+    if fslog::exists(&previous_dir) {
+        info!("Erasing retained previous version...");
+        common::warn_unwrap(fslog::remove_dir_all(&previous_dir));
+    }
     info!("Uninstalling {} items...", paths.len());
     paths.sort_by(|a, b| {
         //sort by length so that a/b/c gets deleted before a, used for folder deletion
@@ -68,9 +78,14 @@ pub fn uninstall_procedure(local_data: &StoredInstallData, quiet: bool) -> Resul
         if meta.is_none() || meta.unwrap().is_directory {
             continue;
         }
-        if !is_hash_whitelisted(&installed.path) && installed.file.hash != defines::HASH_ALWAYS_REPLACE {
+        if installed.file.hash == defines::HASH_SYMLINK {
+            if std::fs::read_link(&installed.path).ok() != installed.file.link_target {
+                warn!("Link target changed, won't delete {:?}", installed.path);
+                continue;
+            }
+        } else if !is_hash_whitelisted(&installed.path) && installed.file.hash != defines::HASH_ALWAYS_REPLACE {
             //can compare hashes, check first
-            let (_, hash_of_fs) = common::get_file_hash(&installed.path)?;
+            let hash_of_fs = common::get_file_hash_with(&installed.path, installed.file.hash_algorithm)?;
             let stored_hash = hex::decode(&installed.file.hash)?;
             if hash_of_fs != stored_hash {
                 //its been changed since the install, do not delete!
@@ -124,5 +139,9 @@ pub fn uninstall_procedure(local_data: &StoredInstallData, quiet: bool) -> Resul
     {
         installer_tools::windows_registry_teardown()?;
     }
+    #[cfg(target_os = "linux")]
+    {
+        installer_tools::linux_xdg_teardown(local_data)?;
+    }
     common::exit(0);
 }
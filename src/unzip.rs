@@ -1,13 +1,16 @@
+use crate::archive;
 use crate::common;
 use crate::common::InstallProgressSegment;
 use crate::fslog;
+use crate::remoteinstallerdata::ArchiveLimits;
+use chrono::{Local, TimeZone};
 use log::debug;
 use pakkly_error::FormattedError;
-use std::convert::TryFrom;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::SeekFrom;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[cfg(unix)]
 use std::fs;
@@ -15,14 +18,12 @@ use std::fs;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-pub fn is_file_zip(path: &PathBuf) -> Result<bool, FormattedError> {
-    let mut file = fslog::file_open(&path)?;
-    let mut buf: [u8; 4] = [0; 4];
-    file.read_exact(&mut buf)?;
-    let zip_magic = [0x50, 0x4B, 0x03, 0x04];
-    return Ok(zip_magic == buf);
-}
-pub fn extract<F>(filename: &PathBuf, target_dir: &PathBuf, progress_cb: F) -> Result<Vec<String>, FormattedError>
+pub fn extract<F>(
+    filename: &PathBuf,
+    target_dir: &PathBuf,
+    limits: &ArchiveLimits,
+    progress_cb: F,
+) -> Result<Vec<String>, FormattedError>
 where
     F: Fn(f32, InstallProgressSegment),
 {
@@ -30,18 +31,22 @@ where
     trim_zip_postfix(&filename)?;
     let file = fslog::file_open(&filename)?;
 
+    let mut validator = archive::EntryValidator::new(limits, file.metadata()?.len());
     let mut archive = zip::ZipArchive::new(file)?;
     let mut dst_files: Vec<String> = Vec::new();
     for i in 0..archive.len() {
         progress_cb(((i as f64) / (archive.len() as f64) * 0.5) as f32, InstallProgressSegment::Installing);
         let mut file = archive.by_index(i)?;
-        let outpath_raw = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
+        let relative = match validator.check(
+            Path::new(&file.name().replace('\\', "/")),
+            file.size(),
+            Some(file.compressed_size()),
+            file.is_dir(),
+        )? {
+            Some(path) => path,
             None => continue,
         };
-        let mut outpath = PathBuf::new();
-        outpath.push(target_dir);
-        outpath.push(outpath_raw);
+        let outpath = archive::output_path(target_dir, &relative)?;
 
         if (&*file.name()).ends_with('/') {
             fslog::create_dir_all(&outpath)?;
@@ -51,8 +56,33 @@ where
                     fslog::create_dir_all(&p)?;
                 }
             }
+            #[cfg(unix)]
+            {
+                //symlinks are stored as entries holding the target path.
+                if file.unix_mode().map_or(false, |mode| mode & 0o170000 == 0o120000) {
+                    let mut link_target = String::new();
+                    file.read_to_string(&mut link_target)?;
+                    archive::check_link_target(&relative, Path::new(&link_target))?;
+                    if fs::symlink_metadata(&outpath).is_ok() {
+                        fslog::remove_file(&outpath)?;
+                    }
+                    std::os::unix::fs::symlink(&link_target, &outpath)?;
+                    dst_files.push(outpath.to_str().unwrap().to_owned());
+                    continue;
+                }
+            }
             let mut outfile = File::create(&outpath)?;
-            std::io::copy(&mut file, &mut outfile)?;
+            //the central directory only claims a size, the stream itself may go on far longer.
+            let declared = file.size();
+            if std::io::copy(&mut Read::by_ref(&mut file).take(declared + 1), &mut outfile)? > declared {
+                return Err(FormattedError::from_str(format!(
+                    "Archive entry {:?} is larger than it declares",
+                    file.name()
+                )));
+            }
+            if let Some(modified) = zip_time(file.last_modified()) {
+                outfile.set_modified(modified)?;
+            }
             dst_files.push(outpath.to_str().unwrap().to_owned());
         }
 
@@ -69,59 +99,286 @@ where
     return Ok(dst_files);
 }
 
+/// Checks the central directory against limits, returns the sum of the uncompressed sizes.
+pub fn validate(filename: &PathBuf, limits: &ArchiveLimits) -> Result<u64, FormattedError> {
+    trim_zip_postfix(&filename)?;
+    let file = fslog::file_open(&filename)?;
+    let mut validator = archive::EntryValidator::new(limits, file.metadata()?.len());
+    let mut archive = zip::ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        validator.check(
+            Path::new(&file.name().replace('\\', "/")),
+            file.size(),
+            Some(file.compressed_size()),
+            file.is_dir(),
+        )?;
+    }
+    return Ok(validator.total_size);
+}
+/// Zip stores DOS timestamps in local time without a zone, None when the entry holds a nonsense date.
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    let local = Local
+        .with_ymd_and_hms(
+            time.year() as i32,
+            time.month() as u32,
+            time.day() as u32,
+            time.hour() as u32,
+            time.minute() as u32,
+            time.second() as u32,
+        )
+        .earliest()?;
+    return Some(SystemTime::from(local));
+}
 fn trim_zip_postfix(zip_path: &PathBuf) -> Result<(), FormattedError> {
     //removes all data after the zip comments.
-    let mut zip = std::fs::OpenOptions::new().read(true).write(true).truncate(false).create(true).open(zip_path)?;
+    let mut zip = std::fs::OpenOptions::new().read(true).write(true).truncate(false).open(zip_path)?;
+    //double check magic
+    let mut buf: [u8; 4] = [0; 4];
+    zip.read_exact(&mut buf)?;
+    let zip_magic = [0x50, 0x4B, 0x03, 0x04];
+    if buf != zip_magic {
+        return Err(FormattedError::from_str("Zip magic not detected!".to_string()));
+    }
+
+    let file_length = zip.metadata()?.len();
+    let end_offset = match find_end_of_central_directory(&mut zip, file_length)? {
+        Some(x) => x,
+        None => {
+            let e = format!("End of zip not found! Malformed zip. Read {} bytes.", file_length);
+            common::submit_basic_crash(e.as_str());
+            return Err(FormattedError::from_str(e.to_string()));
+        }
+    };
+    let record = read_at(&mut zip, end_offset, EOCD_LENGTH)?;
+    let comment_length = le_u16(&record, 20);
+
+    let trimmed_size = end_offset + EOCD_LENGTH as u64 + u64::from(comment_length);
+    if trimmed_size < file_length {
+        debug!("Trimming {} bytes after the end of the zip", file_length - trimmed_size);
+        zip.set_len(trimmed_size)?;
+    }
+    drop(zip);
+
+    Ok(())
+}
+
+const EOCD_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+const EOCD_LENGTH: usize = 22;
+const ZIP64_LOCATOR_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x06, 0x07];
+const ZIP64_LOCATOR_LENGTH: usize = 20;
+const ZIP64_EOCD_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x06, 0x06];
+const ZIP64_EOCD_LENGTH: usize = 56;
+const CENTRAL_HEADER_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x01, 0x02];
+
+/// Scans backward from the end, the signature can just as well appear inside compressed data
+/// or the appended postfix, so every candidate has to point at a central directory ending right before it.
+fn find_end_of_central_directory(zip: &mut File, file_length: u64) -> Result<Option<u64>, FormattedError> {
     let mut buffer: Vec<u8> = Vec::new();
     buffer.resize(1024 * 1024, 0x00);
-    buffer.fill(0x00);
-    let mut offset = 0;
-
-    let mut end_signature_index = 0;
-    let zip_end_signature = [0x50, 0x4b, 0x05, 0x06];
-    'mainloop: loop {
-        if offset == 0 {
-            //double check magic
-            let mut buf: [u8; 4] = [0; 4];
-            zip.read_exact(&mut buf)?;
-            let zip_magic = [0x50, 0x4B, 0x03, 0x04];
-            if buf != zip_magic {
-                return Err(FormattedError::from_str("Zip magic not detected!".to_string()));
+    let mut window_end = file_length;
+    loop {
+        let window_start = window_end.saturating_sub(buffer.len() as u64);
+        let length = (window_end - window_start) as usize;
+        zip.seek(SeekFrom::Start(window_start))?;
+        zip.read_exact(&mut buffer[0..length])?;
+        for i in (0..length.saturating_sub(EOCD_SIGNATURE.len() - 1)).rev() {
+            if buffer[i..i + EOCD_SIGNATURE.len()] == EOCD_SIGNATURE
+                && is_end_of_central_directory(zip, window_start + i as u64, file_length)?
+            {
+                return Ok(Some(window_start + i as u64));
             }
-            offset = 4;
         }
-
-        let length = zip.read(buffer.as_mut_slice())?;
-        if length == 0 {
-            let e = format!("End of zip not found! Malformed zip. Read {} bytes.", offset + length);
-            common::submit_basic_crash(e.as_str());
-            return Err(FormattedError::from_str(e.to_string()));
+        if window_start == 0 {
+            return Ok(None);
         }
-        let slice = buffer[0..length].to_vec();
-        for i in 0..slice.len() {
-            if slice[i] == zip_end_signature[end_signature_index] {
-                end_signature_index += 1; //check the next byte as well
-            } else {
-                end_signature_index = 0; //reset the search!
+        //overlap so a signature split between two windows is still found.
+        window_end = window_start + (EOCD_SIGNATURE.len() - 1) as u64;
+    }
+}
+fn is_end_of_central_directory(zip: &mut File, offset: u64, file_length: u64) -> Result<bool, FormattedError> {
+    if offset + EOCD_LENGTH as u64 > file_length {
+        return Ok(false);
+    }
+    let record = read_at(zip, offset, EOCD_LENGTH)?;
+    if offset + EOCD_LENGTH as u64 + u64::from(le_u16(&record, 20)) > file_length {
+        return Ok(false);
+    }
+    let mut entries = u64::from(le_u16(&record, 10));
+    let mut directory_size = u64::from(le_u32(&record, 12));
+    let mut directory_offset = u64::from(le_u32(&record, 16));
+    let mut directory_end = offset;
+
+    //zip64 keeps the real values in a record found through a locator right before this one.
+    //writers may add it even when the values fit, so its presence rather than the 0xFFFF markers decides.
+    let needs_zip64 = entries == 0xFFFF || directory_size == 0xFFFF_FFFF || directory_offset == 0xFFFF_FFFF;
+    let locator_offset = offset.checked_sub(ZIP64_LOCATOR_LENGTH as u64);
+    let locator = match locator_offset {
+        Some(x) => Some(read_at(zip, x, ZIP64_LOCATOR_LENGTH)?),
+        None => None,
+    };
+    match (locator_offset, locator) {
+        (Some(locator_offset), Some(locator)) if locator[0..4] == ZIP64_LOCATOR_SIGNATURE => {
+            let record64_offset = le_u64(&locator, 8);
+            if record64_offset.saturating_add(ZIP64_EOCD_LENGTH as u64) > locator_offset {
+                return Ok(false);
             }
-            if end_signature_index == zip_end_signature.len() {
-                //found the ZIP End of central directory record
-                offset += i;
-                break 'mainloop;
+            let record64 = read_at(zip, record64_offset, ZIP64_EOCD_LENGTH)?;
+            //the size field counts everything after itself, including any extensible data.
+            if record64[0..4] != ZIP64_EOCD_SIGNATURE
+                || record64_offset.checked_add(12 + le_u64(&record64, 4)) != Some(locator_offset)
+            {
+                return Ok(false);
             }
+            entries = le_u64(&record64, 32);
+            directory_size = le_u64(&record64, 40);
+            directory_offset = le_u64(&record64, 48);
+            directory_end = record64_offset;
         }
-        offset += length;
+        _ if needs_zip64 => return Ok(false),
+        _ => {}
     }
 
-    let mut comment_length_buf: [u8; 2] = [0; 2];
-    let comment_buf_offset = 17 + u64::try_from(offset).unwrap();
-    zip.seek(SeekFrom::Start(comment_buf_offset))?;
-    zip.read_exact(&mut comment_length_buf)?;
-    let comment_length = u16::from_le_bytes(comment_length_buf);
+    if directory_offset.checked_add(directory_size) != Some(directory_end) {
+        return Ok(false);
+    }
+    if entries == 0 {
+        return Ok(directory_size == 0);
+    }
+    return Ok(read_at(zip, directory_offset, 4)?[0..4] == CENTRAL_HEADER_SIGNATURE);
+}
+fn read_at(zip: &mut File, offset: u64, length: usize) -> Result<Vec<u8>, FormattedError> {
+    let mut buf: Vec<u8> = Vec::new();
+    buf.resize(length, 0x00);
+    zip.seek(SeekFrom::Start(offset))?;
+    zip.read_exact(&mut buf)?;
+    return Ok(buf);
+}
+fn le_u16(buf: &[u8], at: usize) -> u16 {
+    return u16::from_le_bytes(buf[at..at + 2].try_into().unwrap());
+}
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    return u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+}
+fn le_u64(buf: &[u8], at: usize) -> u64 {
+    return u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+}
 
-    let trimmed_size = comment_buf_offset + 2 + u64::from(comment_length);
-    zip.set_len(trimmed_size)?;
-    drop(zip);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::write::FileOptions;
 
-    Ok(())
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        //stored, so whatever the data holds ends up in the archive byte for byte.
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        return writer.finish().unwrap().into_inner();
+    }
+    fn to_file(bytes: &[u8]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(bytes).unwrap();
+        return file;
+    }
+    fn find(bytes: &[u8]) -> Option<u64> {
+        return find_end_of_central_directory(&mut to_file(bytes), bytes.len() as u64).unwrap();
+    }
+    /// An end record claiming an empty central directory right before it, valid on its own wherever it is put.
+    fn fake_end_record(offset: u64) -> Vec<u8> {
+        let mut record = EOCD_SIGNATURE.to_vec();
+        record.extend_from_slice(&[0; 8]);
+        record.extend_from_slice(&0u32.to_le_bytes());
+        record.extend_from_slice(&(offset as u32).to_le_bytes());
+        record.extend_from_slice(&0u16.to_le_bytes());
+        return record;
+    }
+    /// Moves the end record values into a zip64 end record and locator, leaving the markers in the classic one.
+    fn to_zip64(zip: &[u8]) -> Vec<u8> {
+        let end = zip.len() - EOCD_LENGTH;
+        let entries = u64::from(le_u16(zip, end + 10));
+        let directory_size = u64::from(le_u32(zip, end + 12));
+        let directory_offset = u64::from(le_u32(zip, end + 16));
+        let mut out = zip[..end].to_vec();
+        let record64_offset = out.len() as u64;
+        out.extend_from_slice(&ZIP64_EOCD_SIGNATURE);
+        out.extend_from_slice(&((ZIP64_EOCD_LENGTH - 12) as u64).to_le_bytes());
+        out.extend_from_slice(&[45, 0, 45, 0]);
+        out.extend_from_slice(&[0; 8]);
+        for value in [entries, entries, directory_size, directory_offset] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&ZIP64_LOCATOR_SIGNATURE);
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&record64_offset.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&EOCD_SIGNATURE);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&[0xFF; 12]);
+        out.extend_from_slice(&0u16.to_le_bytes());
+        return out;
+    }
+
+    #[test]
+    fn end_record_inside_entry_data_is_skipped() {
+        //a complete end record in the data, pointing at a directory that would end right before it.
+        let mut data = b"before".to_vec();
+        data.extend(fake_end_record(0));
+        data.extend_from_slice(b"after");
+        let zip = build_zip(&[("a.txt", b"plain"), ("fake.bin", &data), ("z.txt", b"last")]);
+        let fake = zip.windows(4).position(|x| x == EOCD_SIGNATURE).unwrap();
+        assert!(fake < zip.len() - EOCD_LENGTH);
+        let mut file = to_file(&zip);
+        assert!(!is_end_of_central_directory(&mut file, fake as u64, zip.len() as u64).unwrap());
+        assert_eq!(find(&zip), Some((zip.len() - EOCD_LENGTH) as u64));
+    }
+    #[test]
+    fn appended_postfix_is_trimmed() {
+        let zip = build_zip(&[("a.txt", b"plain")]);
+        let mut with_postfix = zip.clone();
+        with_postfix.extend_from_slice(b"signature blob appended by a code signer ");
+        //looks like an end record of an empty archive, the central directory offset points nowhere.
+        with_postfix.extend(fake_end_record(7));
+        with_postfix.extend_from_slice(&[0x50, 0x4b, 0x05]);
+        assert_eq!(find(&with_postfix), Some((zip.len() - EOCD_LENGTH) as u64));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("postfix.zip");
+        std::fs::write(&path, &with_postfix).unwrap();
+        trim_zip_postfix(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), zip);
+    }
+    #[test]
+    fn zip64_end_record_is_followed() {
+        let zip = to_zip64(&build_zip(&[("a.txt", b"plain"), ("b.txt", b"second")]));
+        let end = (zip.len() - EOCD_LENGTH) as u64;
+        assert_eq!(find(&zip), Some(end));
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut contents = String::new();
+        archive.by_name("b.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "second");
+    }
+    #[test]
+    fn zip64_locator_pointing_elsewhere_is_rejected() {
+        let mut zip = to_zip64(&build_zip(&[("a.txt", b"plain")]));
+        let locator = zip.len() - EOCD_LENGTH - ZIP64_LOCATOR_LENGTH;
+        zip[locator + 8..locator + 16].copy_from_slice(&1u64.to_le_bytes());
+        assert_eq!(find(&zip), None);
+    }
+    #[test]
+    fn signature_split_between_windows_is_found() {
+        let zip = build_zip(&[("a.txt", b"plain")]);
+        let end = zip.len() - EOCD_LENGTH;
+        //the first window starts two bytes into the signature.
+        let window = 1024 * 1024;
+        let mut padded = zip.clone();
+        padded.resize(end + 2 + window, 0);
+        assert_eq!(padded.len() - window, end + 2);
+        assert_eq!(find(&padded), Some(end as u64));
+    }
 }
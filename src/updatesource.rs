@@ -0,0 +1,199 @@
+use crate::common::get_standard_timeout;
+use crate::remoteinstallerdata::{DownloadParams, PakklyMetaRemote};
+use crate::{defines, fslog};
+use log::{info, warn};
+use pakkly_error::FormattedError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the feed document inside a local update directory.
+pub static LOCAL_FEED_FILENAME: &str = "pakkly_feed.json";
+
+/// Where update information and payloads come from. Picked by update_source of the embedded config,
+/// with remote_url as the location, the legacy placeholders stand in for both:
+/// - "pakkly_api" (default): the Pakkly server, which resolves versions itself
+/// - "static_feed": URL of a StaticFeed document on any file host
+/// - "local": a directory holding pakkly_feed.json, or the path of the document itself, as a path or file:// URL
+#[derive(Debug, Clone)]
+pub enum UpdateSource {
+    PakklyApi(String),
+    StaticFeed(String),
+    LocalDirectory(PathBuf),
+}
+/// A self-hosted alternative to the Pakkly API, a single document listing every release.
+/// Entries are ordered newest first, the first one matching the channel and platform is the latest.
+/// Relative download urls are resolved against the location of the feed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StaticFeed {
+    pub background_color: String,
+    pub description: Option<String>,
+    pub app_name: String,
+    pub company_name: Option<String>,
+    pub apps: Vec<FeedEntry>,
+    pub shippers: Vec<FeedEntry>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeedEntry {
+    //missing means every channel
+    pub channel: Option<String>,
+    //"{OS_NAME}_{ARCH_NAME}" such as "linux_x86_64", missing means every platform
+    pub platform: Option<String>,
+    #[serde(flatten)]
+    pub params: DownloadParams,
+}
+impl UpdateSource {
+    pub fn configured() -> UpdateSource {
+        let location = defines::REMOTE_URL_CLEAN.to_string();
+        return match defines::UPDATE_SOURCE_CLEAN.as_str() {
+            "static_feed" => UpdateSource::StaticFeed(location),
+            "local" => UpdateSource::LocalDirectory(local_path(&location).unwrap_or(PathBuf::from(location))),
+            "pakkly_api" => UpdateSource::PakklyApi(location),
+            other => {
+                if let Some(path) = local_path(&location) {
+                    return UpdateSource::LocalDirectory(path);
+                }
+                if !other.is_empty() {
+                    warn!("Unknown update source {:?}, using the Pakkly API", other);
+                }
+                UpdateSource::PakklyApi(location)
+            }
+        };
+    }
+    /// Only the Pakkly API accepts error reports.
+    pub fn crashlog_url(&self) -> Option<String> {
+        return match self {
+            UpdateSource::PakklyApi(base) => Some(format!("{}/api/v1/shipper/error", base)),
+            _ => None,
+        };
+    }
+    pub fn get_update_info(
+        &self,
+        current_app_version: Option<&str>,
+        new_app: Option<String>,
+        new_shipper: Option<String>,
+    ) -> Result<PakklyMetaRemote, FormattedError> {
+        return match self {
+            UpdateSource::PakklyApi(base) => get_api_update_info(base, current_app_version, new_app, new_shipper),
+            UpdateSource::StaticFeed(url) => {
+                info!("Getting update feed: {url}");
+                let response = ureq::get(url).timeout(get_standard_timeout()).call()?.into_string()?;
+                let base = match url.rfind('/') {
+                    Some(i) => url[..i].to_string(),
+                    None => url.clone(),
+                };
+                let feed: StaticFeed = serde_json::from_str(&response)?;
+                resolve_feed(feed, new_app, new_shipper, |x| match x.contains("://") {
+                    true => x.to_string(),
+                    false => format!("{}/{}", base, x.trim_start_matches('/')),
+                })
+            }
+            UpdateSource::LocalDirectory(path) => {
+                let feed_path = match path.is_dir() {
+                    true => path.join(LOCAL_FEED_FILENAME),
+                    false => path.clone(),
+                };
+                info!("Reading update feed: {:?}", feed_path);
+                let base = feed_path.parent().unwrap_or(Path::new("")).to_path_buf();
+                let feed: StaticFeed = serde_json::from_str(&fslog::read_to_string(&feed_path)?)?;
+                resolve_feed(feed, new_app, new_shipper, |x| match x.contains("://") || Path::new(x).is_absolute() {
+                    true => x.to_string(),
+                    false => base.join(x).to_string_lossy().to_string(),
+                })
+            }
+        };
+    }
+}
+fn get_api_update_info(
+    base: &str,
+    current_app_version: Option<&str>,
+    new_app: Option<String>,
+    new_shipper: Option<String>,
+) -> Result<PakklyMetaRemote, FormattedError> {
+    let url = format!("{}/api/v1/shipper/info", base);
+    log::info!("Getting update info: {url}");
+    let mut raw_req = ureq::request("GET", url.as_str())
+        .timeout(get_standard_timeout())
+        .query("app_id", &*defines::PAKKLY_ID_CLEAN)
+        .query("platform_type", defines::PLATFORM_TYPE.to_string().as_str())
+        .query("shipper_version", &*defines::SHIPPER_VERSION_CLEAN)
+        .query("channel", &*defines::SHIPPER_CHANNEL_CLEAN);
+    if let Some(version) = current_app_version {
+        //add the current version
+        raw_req = raw_req.query("app_version", version)
+    }
+    if new_app.is_some() {
+        //requesting specific app version
+        raw_req = raw_req.query("new_app_version", new_app.unwrap().as_str())
+    }
+    if new_shipper.is_some() {
+        //requesting specific shipper version
+        raw_req = raw_req.query("new_shipper_version", new_shipper.unwrap().as_str())
+    }
+    let update_info_request = raw_req.call()?;
+    let update_info_response = update_info_request.into_string()?;
+    let update_info_parsed: PakklyMetaRemote = serde_json::from_str(&update_info_response)?;
+
+    return Ok(update_info_parsed);
+}
+/// Does what the Pakkly API does server side: picks the releases for this channel and platform.
+fn resolve_feed<F>(
+    feed: StaticFeed,
+    new_app: Option<String>,
+    new_shipper: Option<String>,
+    resolve_url: F,
+) -> Result<PakklyMetaRemote, FormattedError>
+where
+    F: Fn(&str) -> String,
+{
+    let mut app = pick_release(&feed.apps, new_app, "app")?;
+    let mut shipper = pick_release(&feed.shippers, new_shipper, "shipper")?;
+    app.url = resolve_url(&app.url);
+    shipper.url = resolve_url(&shipper.url);
+    return Ok(PakklyMetaRemote {
+        background_color: feed.background_color,
+        description: feed.description,
+        shipper,
+        app,
+        app_name: feed.app_name,
+        company_name: feed.company_name,
+    });
+}
+fn pick_release(
+    entries: &Vec<FeedEntry>,
+    version: Option<String>,
+    kind: &str,
+) -> Result<DownloadParams, FormattedError> {
+    let platform = format!("{}_{}", defines::OS_NAME, defines::ARCH_NAME);
+    let found = entries.iter().find(|x| {
+        x.channel.as_ref().map_or(true, |c| *c == *defines::SHIPPER_CHANNEL_CLEAN)
+            && x.platform.as_ref().map_or(true, |p| *p == platform)
+            && version.as_ref().map_or(true, |v| *v == x.params.version)
+    });
+    return match found {
+        Some(x) => Ok(x.params.clone()),
+        None => Err(FormattedError::from_str(format!(
+            "Update feed has no {} release{} for channel {} on {}",
+            kind,
+            version.map(|v| format!(" {}", v)).unwrap_or_default(),
+            *defines::SHIPPER_CHANNEL_CLEAN,
+            platform
+        ))),
+    };
+}
+/// The path behind a file:// URL or an absolute path, None for anything to be fetched over the network.
+pub fn local_path(location: &str) -> Option<PathBuf> {
+    if let Some(rest) = location.strip_prefix("file://") {
+        //file:///C:/dir on windows
+        #[cfg(target_os = "windows")]
+        {
+            if rest.starts_with('/') && rest.chars().nth(2) == Some(':') {
+                return Some(PathBuf::from(&rest[1..]));
+            }
+        }
+        return Some(PathBuf::from(rest));
+    }
+    if !location.contains("://") && Path::new(location).is_absolute() {
+        return Some(PathBuf::from(location));
+    }
+    return None;
+}
@@ -0,0 +1,184 @@
+use crate::common;
+use crate::remoteinstallerdata::{HashAlgorithm, InstalledFile, StoredInstallData};
+use crate::{defines, fslog, paths};
+use log::{info, warn};
+use pakkly_error::FormattedError;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Outcome of rehashing an install against the stored file lists. Paths are relative to the
+/// program directory for app files and absolute for meta files.
+#[derive(Serialize, Debug, Default)]
+pub struct VerifyReport {
+    pub app_version: String,
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    pub extra: Vec<String>,
+    //stored hashes that differ from the server manifest of the installed version.
+    pub manifest_mismatch: Vec<String>,
+}
+impl VerifyReport {
+    pub fn is_intact(&self) -> bool {
+        return self.missing.is_empty()
+            && self.modified.is_empty()
+            && self.extra.is_empty()
+            && self.manifest_mismatch.is_empty();
+    }
+    /// Every kind of problem sets its own bit, see defines::VERIFY_EXIT_*
+    pub fn exit_code(&self) -> i32 {
+        let mut code = 0;
+        if !self.missing.is_empty() {
+            code |= defines::VERIFY_EXIT_MISSING;
+        }
+        if !self.modified.is_empty() {
+            code |= defines::VERIFY_EXIT_MODIFIED;
+        }
+        if !self.extra.is_empty() {
+            code |= defines::VERIFY_EXIT_EXTRA;
+        }
+        if !self.manifest_mismatch.is_empty() {
+            code |= defines::VERIFY_EXIT_MANIFEST;
+        }
+        return code;
+    }
+    pub fn to_human(&self) -> String {
+        let mut out = format!("Verified {} files of version {}\n", self.checked, self.app_version);
+        let lists = [
+            ("Missing", &self.missing),
+            ("Modified", &self.modified),
+            ("Extra", &self.extra),
+            ("Differing from the server manifest", &self.manifest_mismatch),
+        ];
+        for (title, list) in lists {
+            if list.is_empty() {
+                continue;
+            }
+            out += &format!("{} ({}):\n", title, list.len());
+            for path in list {
+                out += &format!("  {}\n", path);
+            }
+        }
+        if self.is_intact() {
+            out += "Installation is intact.\n";
+        }
+        return out;
+    }
+}
+enum FileState {
+    Intact,
+    Missing,
+    Modified,
+}
+pub fn verify_installation(params: &StoredInstallData) -> Result<VerifyReport, FormattedError> {
+    let mut report = VerifyReport { app_version: params.installed_app_info.version.clone(), ..Default::default() };
+    let program_dir = paths::get_install_path();
+    let json_path = paths::get_json_path();
+
+    for file in &params.installed_files {
+        match check_file(&program_dir.join(&file.dst_path), &file.hash, file.hash_algorithm, file.link_target.as_ref())?
+        {
+            FileState::Intact => {}
+            FileState::Missing => report.missing.push(file.dst_path_human.clone()),
+            FileState::Modified => report.modified.push(file.dst_path_human.clone()),
+        }
+        report.checked += 1;
+    }
+    for file in &params.installed_files_meta {
+        let absolute = meta_path(file);
+        //rewritten on every launch, its stored hash is always stale.
+        let hash = match absolute == json_path {
+            true => defines::HASH_ALWAYS_REPLACE,
+            false => file.hash.as_str(),
+        };
+        match check_file(&absolute, hash, file.hash_algorithm, file.link_target.as_ref())? {
+            FileState::Intact => {}
+            FileState::Missing => report.missing.push(file.dst_path_human.clone()),
+            FileState::Modified => report.modified.push(file.dst_path_human.clone()),
+        }
+        report.checked += 1;
+    }
+
+    if let Some(manifest) = &params.installed_app_info.manifest {
+        let expected: HashMap<&str, &str> = manifest.iter().map(|x| (x.path.as_str(), x.blake3.as_str())).collect();
+        for file in &params.installed_files {
+            if file.hash_algorithm != HashAlgorithm::blake3 {
+                continue; //not migrated yet, nothing to compare
+            }
+            let path = file.dst_path.to_string_lossy().replace('\\', "/");
+            if let Some(hash) = expected.get(path.as_str()) {
+                if !hash.eq_ignore_ascii_case(&file.hash) {
+                    report.manifest_mismatch.push(file.dst_path_human.clone());
+                }
+            }
+        }
+    }
+
+    let known: HashSet<&PathBuf> = params.installed_files.iter().map(|x| &x.dst_path).collect();
+    if fslog::exists(&program_dir) {
+        for file in common::all_relative_files_in_folder_recursive(&program_dir)? {
+            if !known.contains(&file) {
+                report.extra.push(file.to_string_lossy().to_string());
+            }
+        }
+    }
+    report.extra.sort();
+    info!(
+        "Verification done: {} missing, {} modified, {} extra.",
+        report.missing.len(),
+        report.modified.len(),
+        report.extra.len()
+    );
+    return Ok(report);
+}
+fn meta_path(file: &InstalledFile) -> PathBuf {
+    let mut absolute = PathBuf::new();
+    if let Some(root) = &file.root {
+        absolute.push(root);
+    }
+    absolute.push(&file.dst_path);
+    return absolute;
+}
+fn check_file(
+    absolute: &PathBuf,
+    expected_hash: &str,
+    algorithm: HashAlgorithm,
+    link_target: Option<&PathBuf>,
+) -> Result<FileState, FormattedError> {
+    let meta = match fslog::get_simple_fs_meta_symlink(absolute) {
+        Some(x) => x,
+        None => return Ok(FileState::Missing),
+    };
+    if expected_hash == defines::HASH_DIRECTORY {
+        return Ok(match meta.is_directory {
+            true => FileState::Intact,
+            false => FileState::Modified,
+        });
+    }
+    if expected_hash == defines::HASH_SYMLINK {
+        //links have no contents of their own, only where they point matters.
+        return Ok(match std::fs::read_link(absolute).ok().as_ref() == link_target {
+            true => FileState::Intact,
+            false => FileState::Modified,
+        });
+    }
+    if expected_hash == defines::HASH_ALWAYS_REPLACE || expected_hash == defines::HASH_DEFER {
+        //no content hash to compare against, existing is all that can be checked.
+        return Ok(FileState::Intact);
+    }
+    if meta.is_directory {
+        return Ok(FileState::Modified);
+    }
+    let hash = match common::get_file_hash_with(absolute, algorithm) {
+        Ok(bytes) => hex::encode(bytes),
+        Err(e) => {
+            warn!("Could not hash {:?}: {:?}", absolute, e);
+            return Ok(FileState::Modified);
+        }
+    };
+    return Ok(match hash == expected_hash {
+        true => FileState::Intact,
+        false => FileState::Modified,
+    });
+}
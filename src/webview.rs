@@ -1,5 +1,6 @@
 use std::{
-    sync::Mutex,
+    sync::{Arc, Mutex},
+    thread,
     time::{Duration, SystemTime},
 };
 
@@ -11,6 +12,7 @@ use crate::{
 };
 use common::CrashState;
 use lazy_static::lazy_static;
+use log::info;
 use web_view::*;
 pub struct Webview<'a> {
     webview: WebView<'a, CrashState>,
@@ -104,3 +106,65 @@ fn set_download_progress_direct(
 fn set_scene_direct(webview: &mut WebView<CrashState>, scene_id: SceneID, app_name: String) -> WVResult {
     webview.eval(&format!("setSceneID({},\"{}\")", scene_id as i32, app_name.replace("\"", "\\\"")))
 }
+
+/// The updater window, see frontend::run_install
+pub fn run_install<F>(local_data: &StoredInstallData, install: F) -> CrashState
+where
+    F: FnOnce(&dyn Fn(f32, InstallProgressSegment)) -> CrashState + Send + 'static,
+{
+    let should_download = Arc::new(Mutex::new(false));
+    let thread_should = should_download.clone();
+
+    let wv = Webview::new(html_embed::UPDATER_UI, local_data, || {
+        *should_download.lock().unwrap() = true;
+    });
+    let handle = wv.create_handle();
+    let handle_mutex_t1 = Arc::new(Mutex::new(handle));
+    let handle_mutex_t2 = handle_mutex_t1.clone();
+    let handle_mutex_t3 = handle_mutex_t1.clone();
+    let handle_mutex_panic = handle_mutex_t1.clone();
+    let handle_mutex_netcrash = handle_mutex_t1.clone();
+    std::panic::set_hook(Box::new(move |pi| {
+        common::emit_panic_error(&pi);
+
+        let h = handle_mutex_panic.lock().unwrap();
+        let attempt = h.dispatch(move |a| {
+            *a.user_data_mut() = CrashState::FatalError;
+            a.exit();
+            return Ok(());
+        });
+        if attempt.is_err() {
+            //try one last time
+            common::submit_critical_error();
+            common::exit(1);
+        }
+    }));
+
+    thread::spawn(move || loop {
+        if *thread_should.lock().unwrap() {
+            info!("Threaded download starting!");
+            let cs = install(&move |progress, segment| {
+                Webview::set_download_progress(&handle_mutex_t2.lock().unwrap(), segment, progress);
+            });
+            if *defines::FRESH_INSTALL {
+                thread::sleep(Duration::from_millis(300));
+                Webview::set_download_progress(
+                    &handle_mutex_t3.lock().unwrap(),
+                    InstallProgressSegment::Installing,
+                    1.0,
+                );
+                thread::sleep(Duration::from_millis(1500));
+            }
+            let h = &handle_mutex_netcrash.lock().unwrap();
+            h.dispatch(move |a| {
+                *a.user_data_mut() = cs;
+                a.exit();
+                return Ok(());
+            })
+            .unwrap();
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    });
+    return wv.run().unwrap();
+}
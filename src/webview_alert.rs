@@ -1,31 +1,10 @@
+use crate::frontend::ConfirmParams;
 use lazy_static::lazy_static;
 use web_view::*;
 
 lazy_static! {
     static ref HTML_CONTENT_CONFIRM: &'static str = html_embed::CONFIRM;
 }
-pub struct ConfirmParams {
-    pub title: String,
-    pub body: String,
-    pub image: ConfirmImage,
-    pub yes_str: String,
-    pub no_str: String,
-}
-#[derive(Copy, Clone)]
-pub enum ConfirmImage {
-    NoInternet = 0,
-    Error = 1,
-    Question = 2,
-}
-pub fn alert(title: &str, body: &str, img: Option<ConfirmImage>) -> bool {
-    return confirm(ConfirmParams {
-        title: title.to_owned(),
-        body: body.to_owned(),
-        image: img.unwrap_or(ConfirmImage::Error),
-        yes_str: "Ok".to_string(),
-        no_str: "".to_string(),
-    });
-}
 pub fn confirm(params: ConfirmParams) -> bool {
     let mut wv = web_view::builder()
         .title("")
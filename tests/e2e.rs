@@ -0,0 +1,198 @@
+//! End to end runs of the shipper: installs, updates and uninstalls against mocker/mockserver.
+//!
+//! Linux only, the sandbox relies on XDG_DATA_HOME deciding the install root and self-updates
+//! replacing the running binary in place. --pakkly_noroot keeps the runs away from /usr/share.
+#![cfg(target_os = "linux")]
+//explicit returns, like the shipper itself.
+#![allow(clippy::needless_return)]
+mod support;
+
+use std::os::unix::fs::PermissionsExt;
+use support::{installed_paths, set, Sandbox};
+
+/// Installs app 1.0.0 and shipper 1.0.0 the way the website download would.
+fn installed(name: &str, files: &[(&str, &str)]) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    sandbox.publish_app("1.0.0", files);
+    sandbox.publish_shipper("1.0.0");
+    let installer = sandbox.installer("1.0.0");
+    sandbox.run_ok(&installer, &["--pakkly_install_quiet", "--pakkly_noroot"]);
+    return sandbox;
+}
+
+#[test]
+fn fresh_quiet_install() {
+    let sandbox = installed("fresh", &[("data.txt", "one"), ("lib/nested.txt", "nested")]);
+
+    assert_eq!(sandbox.program_files(), set(&["bin/app", "data.txt", "lib/nested.txt", "shipperfile.json"]));
+    assert_eq!(sandbox.read_program_file("lib/nested.txt"), "nested");
+    let app_mode = std::fs::metadata(sandbox.program_dir().join("bin/app")).unwrap().permissions().mode();
+    assert_eq!(app_mode & 0o111, 0o111, "the app lost its executable bit");
+
+    let shipper = sandbox.installed_shipper();
+    assert!(shipper.is_file(), "no shipper at {:?}", shipper);
+    let version = sandbox.run_ok(&shipper, &["--pakkly_version"]);
+    assert_eq!(String::from_utf8_lossy(&version.stdout).trim(), "1.0.0");
+
+    let stored = sandbox.installer_json();
+    assert_eq!(stored["installing"], false);
+    assert_eq!(stored["installed_app_info"]["version"], "1.0.0");
+    assert_eq!(stored["fetched_meta"]["shipper"]["version"], "1.0.0");
+    assert_eq!(stored["fetched_meta"]["app_name"], support::APP_NAME);
+    assert_eq!(stored["shipperfile"]["program_path_to_binary"], "bin/app");
+    assert!(stored["pending_install"].is_null());
+    assert_eq!(installed_paths(&stored), set(&["bin/app", "data.txt", "lib/nested.txt", "shipperfile.json"]));
+
+    let requests = sandbox.requests();
+    let info = requests.iter().find(|x| x["path"] == "/api/v1/shipper/info").expect("update info was never asked for");
+    assert_eq!(info["query"]["app_id"], sandbox.app_id.as_str());
+    assert_eq!(info["query"]["shipper_version"], "1.0.0");
+    assert!(sandbox.requested("/payloads/app/1.0.0.zip"));
+    assert!(sandbox.requested("/payloads/shipper/1.0.0.zip"));
+    //quiet installs never start the app.
+    assert!(sandbox.launches().is_empty());
+}
+
+#[test]
+fn diff_update_adds_and_removes_files() {
+    let sandbox = installed("diff", &[("kept.txt", "same"), ("changed.txt", "old"), ("removed.txt", "gone soon")]);
+    sandbox.publish_app("2.0.0", &[("kept.txt", "same"), ("changed.txt", "new"), ("added/file.txt", "added")]);
+    sandbox.expire_update_cache();
+    //modes come from the archive, read-only files are replaced all the same.
+    let changed = sandbox.program_dir().join("changed.txt");
+    std::fs::set_permissions(&changed, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+    sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_install_quiet", "--pakkly_noroot"]);
+
+    assert_eq!(
+        sandbox.program_files(),
+        set(&["added/file.txt", "bin/app", "changed.txt", "kept.txt", "shipperfile.json"])
+    );
+    assert_eq!(sandbox.read_program_file("changed.txt"), "new");
+    assert_eq!(sandbox.read_program_file("added/file.txt"), "added");
+
+    let stored = sandbox.installer_json();
+    assert_eq!(stored["installing"], false);
+    assert_eq!(stored["installed_app_info"]["version"], "2.0.0");
+    assert_eq!(
+        installed_paths(&stored),
+        set(&["added/file.txt", "bin/app", "changed.txt", "kept.txt", "shipperfile.json"])
+    );
+    //what the update replaced or removed is kept for --pakkly_rollback.
+    assert_eq!(stored["previous_version"]["app_info"]["version"], "1.0.0");
+    let previous = support::files_below(&sandbox.install_root().join("previous"));
+    assert!(previous.contains("removed.txt"), "removed.txt was not retained: {:?}", previous);
+    assert!(previous.contains("changed.txt"), "changed.txt was not retained: {:?}", previous);
+    assert!(!sandbox.install_root().join("staging").exists());
+}
+
+#[test]
+fn diff_update_turns_files_into_directories_and_back() {
+    let sandbox = installed("typechange", &[("swap", "a file"), ("folder/inner.txt", "inner")]);
+    sandbox.publish_app("2.0.0", &[("swap/inside.txt", "now a directory"), ("folder", "now a file")]);
+    sandbox.expire_update_cache();
+
+    sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_install_quiet", "--pakkly_noroot"]);
+
+    assert_eq!(sandbox.program_files(), set(&["bin/app", "folder", "shipperfile.json", "swap/inside.txt"]));
+    assert_eq!(sandbox.read_program_file("swap/inside.txt"), "now a directory");
+    assert_eq!(sandbox.read_program_file("folder"), "now a file");
+    let stored = sandbox.installer_json();
+    assert_eq!(stored["installed_app_info"]["version"], "2.0.0");
+    assert_eq!(installed_paths(&stored), set(&["bin/app", "folder", "shipperfile.json", "swap/inside.txt"]));
+    //both sides of the change are moved aside rather than erased.
+    let previous = support::files_below(&sandbox.install_root().join("previous"));
+    assert!(previous.contains("swap"), "swap was not retained: {:?}", previous);
+    assert!(previous.contains("folder/inner.txt"), "folder/inner.txt was not retained: {:?}", previous);
+}
+
+#[test]
+fn installexact_app_installs_an_older_version() {
+    let sandbox = Sandbox::new("exact");
+    sandbox.publish_app("1.0.0", &[("v1.txt", "one")]);
+    sandbox.publish_app("2.0.0", &[("v2.txt", "two")]);
+    sandbox.publish_shipper("1.0.0");
+    let installer = sandbox.installer("1.0.0");
+    sandbox.run_ok(&installer, &["--pakkly_install_quiet", "--pakkly_noroot"]);
+    assert_eq!(sandbox.installer_json()["installed_app_info"]["version"], "2.0.0");
+
+    //forced installs skip the update cache, no need to expire it.
+    sandbox.run_ok(
+        &sandbox.installed_shipper(),
+        &["--pakkly_installexact_app", "1.0.0", "--pakkly_install_quiet", "--pakkly_noroot"],
+    );
+
+    assert_eq!(sandbox.program_files(), set(&["bin/app", "shipperfile.json", "v1.txt"]));
+    let stored = sandbox.installer_json();
+    assert_eq!(stored["installed_app_info"]["version"], "1.0.0");
+    assert_eq!(stored["fetched_meta"]["app"]["version"], "1.0.0");
+    assert_eq!(installed_paths(&stored), set(&["bin/app", "shipperfile.json", "v1.txt"]));
+    let asked = sandbox.requests().into_iter().any(|x| x["query"]["new_app_version"] == "1.0.0");
+    assert!(asked, "the exact version was never asked for");
+}
+
+#[test]
+fn self_update_replaces_the_installed_shipper() {
+    let sandbox = installed("selfupdate", &[("data.txt", "one")]);
+    sandbox.publish_shipper("2.0.0");
+    sandbox.expire_update_cache();
+    //left behind by an older shipper archive.
+    std::fs::write(sandbox.install_root().join("runner/stale.txt"), "old").unwrap();
+
+    //a regular launch: the app is current, so the shipper updates itself and starts the app.
+    sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_noroot"]);
+
+    assert!(sandbox.requested("/payloads/shipper/2.0.0.zip"));
+    assert_eq!(sandbox.launches(), vec!["1.0.0".to_string()]);
+    let version = sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_version"]);
+    assert_eq!(String::from_utf8_lossy(&version.stdout).trim(), "2.0.0");
+
+    //replacing the shipper must leave the rest of the install alone.
+    let stored = sandbox.installer_json();
+    assert_eq!(stored["installed_app_info"]["version"], "1.0.0");
+    assert_eq!(stored["fetched_meta"]["shipper"]["version"], "2.0.0");
+    assert_eq!(sandbox.program_files(), set(&["bin/app", "data.txt", "shipperfile.json"]));
+    assert!(sandbox.install_root().join("runner/OSS_LICENSES.txt").is_file());
+    assert!(!sandbox.install_root().join("runner/stale.txt").exists());
+}
+
+#[test]
+fn uninstall_quiet_removes_the_install() {
+    let sandbox = installed("uninstall", &[("data.txt", "one"), ("lib/nested.txt", "nested")]);
+    //files the user made are not the shipper's to delete.
+    std::fs::write(sandbox.program_dir().join("user_notes.txt"), "mine").unwrap();
+
+    sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_uninstall_quiet", "--pakkly_noroot"]);
+
+    assert!(!sandbox.installer_json_path().exists());
+    assert!(!sandbox.installed_shipper().exists());
+    assert_eq!(support::files_below(&sandbox.install_root()), set(&["program/user_notes.txt"]));
+    assert!(sandbox.launches().is_empty());
+}
+
+#[test]
+fn headless_frontend_installs_and_updates() {
+    let sandbox = Sandbox::new("headless");
+    sandbox.publish_app("1.0.0", &[("data.txt", "one")]);
+    sandbox.publish_shipper("1.0.0");
+    let installer = sandbox.installer("1.0.0");
+
+    //without a terminal or display headless gets picked anyway, but a fresh install only goes ahead when asked for.
+    let declined = sandbox.run(&installer, &["--pakkly_noroot"]);
+    assert!(String::from_utf8_lossy(&declined.stderr).contains("Not installing E2E App"));
+    assert!(sandbox.launches().is_empty());
+
+    let output = sandbox.run_ok(&installer, &["--pakkly_frontend", "headless", "--pakkly_noroot"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Installing E2E App..."));
+    assert_eq!(sandbox.installer_json()["installed_app_info"]["version"], "1.0.0");
+    assert_eq!(sandbox.launches(), vec!["1.0.0".to_string()]);
+
+    sandbox.publish_app("2.0.0", &[("data.txt", "two")]);
+    sandbox.expire_update_cache();
+    sandbox.run_ok(&sandbox.installed_shipper(), &["--pakkly_frontend", "headless", "--pakkly_noroot", "--opened"]);
+
+    assert_eq!(sandbox.read_program_file("data.txt"), "two");
+    assert_eq!(sandbox.installer_json()["installed_app_info"]["version"], "2.0.0");
+    //the flag and its value stay with the shipper, the app only gets its own arguments.
+    assert_eq!(sandbox.launches(), vec!["1.0.0".to_string(), "2.0.0 --opened".to_string()]);
+}
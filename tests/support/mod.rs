@@ -0,0 +1,265 @@
+//! Runs the shipper binary against mocker/mockserver inside a throwaway HOME.
+//!
+//! The helper crates are built once per test run with the cargo running the tests, release builds so
+//! zipping the debug shipper for a payload stays quick.
+#![allow(dead_code)]
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+pub static APP_NAME: &str = "E2E App";
+/// APP_NAME as the installed shipper is named on linux
+pub static SHIPPER_FILENAME: &str = "E2E_App";
+/// Every run of the shipper gets killed after this long, a stuck run must fail the test rather than hang it.
+static RUN_TIMEOUT: Duration = Duration::from_secs(180);
+
+static MOCKSERVER: OnceLock<PathBuf> = OnceLock::new();
+static STAMPER: OnceLock<PathBuf> = OnceLock::new();
+
+fn repo() -> &'static Path {
+    return Path::new(env!("CARGO_MANIFEST_DIR"));
+}
+/// Builds the crate at manifest_dir and returns its binary.
+fn tool(cell: &'static OnceLock<PathBuf>, manifest_dir: &str, name: &str) -> &'static Path {
+    return cell.get_or_init(|| {
+        let manifest = repo().join(manifest_dir).join("Cargo.toml");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--release", "--quiet", "--manifest-path"])
+            .arg(&manifest)
+            .status()
+            .expect("cargo could not be started");
+        assert!(status.success(), "building {:?} failed", manifest);
+        return repo().join(manifest_dir).join("target/release").join(name);
+    });
+}
+
+/// One mock server, its release folders and the HOME the shipper installs into.
+pub struct Sandbox {
+    pub dir: TempDir,
+    pub url: String,
+    pub app_id: String,
+    server: Child,
+}
+impl Sandbox {
+    pub fn new(name: &str) -> Sandbox {
+        let dir = tempfile::Builder::new().prefix(&format!("shipper_e2e_{}_", name)).tempdir().unwrap();
+        for folder in ["apps", "shippers", "home"] {
+            fs::create_dir_all(dir.path().join(folder)).unwrap();
+        }
+        let mockserver = tool(&MOCKSERVER, "mocker/mockserver", "mockserver");
+        let mut server = Command::new(mockserver)
+            .args(["--port", "0", "--app-name", APP_NAME, "--apps"])
+            .arg(dir.path().join("apps"))
+            .arg("--shippers")
+            .arg(dir.path().join("shippers"))
+            .arg("--record")
+            .arg(dir.path().join("requests.jsonl"))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("mockserver could not be started");
+        //the first line is the address it listens on.
+        let mut url = String::new();
+        BufReader::new(server.stdout.take().unwrap()).read_line(&mut url).unwrap();
+        return Sandbox { url: url.trim().to_string(), app_id: format!("e2e_{}", name), dir, server };
+    }
+    pub fn path(&self) -> &Path {
+        return self.dir.path();
+    }
+
+    /// Publishes an app version: files as given, plus a shipperfile.json and bin/app,
+    /// a script that appends its arguments to launches.log.
+    pub fn publish_app(&self, version: &str, files: &[(&str, &str)]) {
+        let root = self.path().join("apps").join(version);
+        for (path, contents) in files {
+            write(&root.join(path), contents);
+        }
+        let shipperfile = serde_json::json!({
+            "app_id": self.app_id,
+            "program_path_to_binary": "bin/app",
+            "program_arguments": null,
+            "program_working_subdirectory": null,
+            "instance_mode": null,
+            "mime_types": null,
+            "mime_definitions": null,
+            "url_schemes": null,
+            "health_check": null,
+            "preserved_files": null,
+            "_generated": {"icon": "data:image/png;base64,"}
+        });
+        write(&root.join("shipperfile.json"), &shipperfile.to_string());
+        let app = root.join("bin/app");
+        write(&app, &format!("#!/bin/sh\necho \"{} $*\" >> \"$E2E_LAUNCH_LOG\"\n", version));
+        #[cfg(unix)]
+        fs::set_permissions(&app, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    /// Publishes the shipper under test, stamped to report version.
+    pub fn publish_shipper(&self, version: &str) {
+        let folder = self.path().join("shippers").join(version);
+        fs::create_dir_all(&folder).unwrap();
+        self.stamped_shipper(version, &folder.join("shipper"));
+    }
+    /// A copy of the shipper under test reporting version, the way a download from the website would be.
+    pub fn installer(&self, version: &str) -> PathBuf {
+        let path = self.path().join("installer");
+        self.stamped_shipper(version, &path);
+        return path;
+    }
+    fn stamped_shipper(&self, version: &str, destination: &Path) {
+        fs::copy(env!("CARGO_BIN_EXE_shipper"), destination).unwrap();
+        let config = self.path().join("stamp.json");
+        write(&config, &serde_json::json!({ "shipper_version": version }).to_string());
+        let stamper = tool(&STAMPER, "build_tools/stamper", "stamper");
+        let output = Command::new(stamper).arg("config").arg(destination).arg(&config).output().unwrap();
+        assert!(output.status.success(), "stamping failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    /// Runs executable with a HOME of its own, talking to the mock server. The log goes to the test output.
+    pub fn run(&self, executable: &Path, args: &[&str]) -> Output {
+        let home = self.path().join("home");
+        let mut command = Command::new(executable);
+        command
+            .args(args)
+            .env("HOME", &home)
+            .env("XDG_DATA_HOME", home.join(".local/share"))
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .env("XDG_CACHE_HOME", home.join(".cache"))
+            //the debug overrides of config::DebugOverrides, the install root is left to XDG_DATA_HOME.
+            .env("SHIPPER_REMOTE_URL", &self.url)
+            .env("SHIPPER_APP_ID", &self.app_id)
+            .env("SHIPPER_CHANNEL", "stable")
+            .env("SHIPPER_UPDATE_SOURCE", "pakkly_api")
+            .env_remove("SHIPPER_INSTALL_ROOT")
+            .env_remove("SHIPPER_OVERRIDE_FILE")
+            .env("E2E_LAUNCH_LOG", self.path().join("launches.log"))
+            //no display and no terminal: anything that is not quiet runs with the headless frontend.
+            .env_remove("DISPLAY")
+            .env_remove("WAYLAND_DISPLAY")
+            .stdin(Stdio::null());
+        //files rather than pipes, nobody reads the log while the shipper writes it.
+        let (stdout, stderr) = (self.path().join("run.out"), self.path().join("run.log"));
+        command.stdout(File::create(&stdout).unwrap()).stderr(File::create(&stderr).unwrap());
+        let child = command.spawn().expect("shipper could not be started");
+        let status = wait_with_timeout(child, &stderr);
+        let output = Output { status, stdout: fs::read(&stdout).unwrap(), stderr: fs::read(&stderr).unwrap() };
+        eprintln!("--- {:?} {:?}\n{}", executable, args, String::from_utf8_lossy(&output.stderr));
+        return output;
+    }
+    /// Like run, failing the test unless the shipper exits successfully.
+    pub fn run_ok(&self, executable: &Path, args: &[&str]) -> Output {
+        let output = self.run(executable, args);
+        assert!(output.status.success(), "{:?} {:?} exited with {}", executable, args, output.status);
+        return output;
+    }
+
+    pub fn install_root(&self) -> PathBuf {
+        return self.path().join("home/.local/share").join(format!("pakked_{}", self.app_id));
+    }
+    pub fn installed_shipper(&self) -> PathBuf {
+        return self.install_root().join("runner").join(SHIPPER_FILENAME);
+    }
+    pub fn program_dir(&self) -> PathBuf {
+        return self.install_root().join("program");
+    }
+    pub fn installer_json_path(&self) -> PathBuf {
+        return self.install_root().join("runner/pakkly.installer.json");
+    }
+    pub fn installer_json(&self) -> Value {
+        let raw = fs::read_to_string(self.installer_json_path()).expect("pakkly.installer.json is missing");
+        return serde_json::from_str(&raw).unwrap();
+    }
+    /// Lets the next launch check for updates again, as if PAKKLY_CACHE_SEC had passed.
+    pub fn expire_update_cache(&self) {
+        let mut stored = self.installer_json();
+        stored["last_launch"] = Value::from(0);
+        fs::write(self.installer_json_path(), stored.to_string()).unwrap();
+    }
+    /// Every file below the program directory, relative and "/" separated.
+    pub fn program_files(&self) -> BTreeSet<String> {
+        return files_below(&self.program_dir());
+    }
+    pub fn read_program_file(&self, relative: &str) -> String {
+        return fs::read_to_string(self.program_dir().join(relative)).unwrap();
+    }
+    pub fn launches(&self) -> Vec<String> {
+        return match fs::read_to_string(self.path().join("launches.log")) {
+            Ok(x) => x.lines().map(|x| x.trim().to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+    }
+    /// What the mock server received, oldest first.
+    pub fn requests(&self) -> Vec<Value> {
+        let raw = fs::read_to_string(self.path().join("requests.jsonl")).unwrap_or_default();
+        return raw.lines().map(|x| serde_json::from_str(x).unwrap()).collect();
+    }
+    pub fn requested(&self, path: &str) -> bool {
+        return self.requests().iter().any(|x| x["path"] == path);
+    }
+}
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _e = self.server.kill();
+        let _e = self.server.wait();
+    }
+}
+
+/// Regular files and symlinks below root, directories are left out.
+pub fn files_below(root: &Path) -> BTreeSet<String> {
+    let mut found = BTreeSet::new();
+    if !root.exists() {
+        return found;
+    }
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        for entry in fs::read_dir(&folder).unwrap().flatten() {
+            if entry.file_type().unwrap().is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+            found.insert(relative);
+        }
+    }
+    return found;
+}
+pub fn set(items: &[&str]) -> BTreeSet<String> {
+    return items.iter().map(|x| x.to_string()).collect();
+}
+/// The dst_path of every entry of installed_files in pakkly.installer.json.
+pub fn installed_paths(stored: &Value) -> BTreeSet<String> {
+    return stored["installed_files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["dst_path"].as_str().unwrap().replace('\\', "/"))
+        .collect();
+}
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+fn wait_with_timeout(mut child: Child, log: &Path) -> ExitStatus {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if started.elapsed() > RUN_TIMEOUT {
+            let _e = child.kill();
+            let _e = child.wait();
+            let log = fs::read_to_string(log).unwrap_or_default();
+            panic!("shipper did not exit within {:?}:\n{}", RUN_TIMEOUT, log);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}